@@ -2,7 +2,7 @@ use std::{io::Write, time::Duration};
 
 use escl::{
     discover,
-    settings::{ContentRegionUnits, InputSource, ScanRegion, ScanRegions},
+    settings::{ContentRegionUnits, ScanRegion, ScanRegions},
     Scanner,
 };
 
@@ -29,15 +29,23 @@ async fn main() {
         .expect("unable to fetch capabilities");
     println!("Scanner capabilities: \n{:#?}", capabilities);
 
+    let input_source = *capabilities
+        .input_sources()
+        .first()
+        .expect("scanner has no input source");
+    let input_caps = capabilities
+        .input_caps(input_source)
+        .expect("input source without capabilities");
+
     let job = scanner
         .scan(&escl::settings::ScanSettings {
-            version: capabilities.version,
+            version: capabilities.version.clone(),
             intent: None,
             scan_regions: Some(ScanRegions {
                 scan_region: ScanRegion {
-                    height: capabilities.platen.platen_input_caps.max_height,
+                    height: input_caps.max_height,
                     content_region_units: ContentRegionUnits::ThreeHundredthsOfInches,
-                    width: capabilities.platen.platen_input_caps.max_width,
+                    width: input_caps.max_width,
                     x_offset: 0,
                     y_offset: 0,
                 },
@@ -45,15 +53,21 @@ async fn main() {
             document_format_ext: Some("image/jpeg".to_owned()),
             x_resolution: None,
             y_resolution: None,
-            input_source: Some(InputSource::Platen),
+            input_source: Some(input_source),
+            duplex: None,
             color_mode: capabilities
-                .platen
-                .platen_input_caps
-                .setting_profiles
-                .setting_profile
-                .color_modes
-                .color(),
+                .setting_profiles(input_source)
+                .iter()
+                .find_map(|profile| profile.color_modes.color()),
+            brightness: None,
             compression_factor: None,
+            contrast: None,
+            gamma: None,
+            highlight: None,
+            noise_removal: None,
+            shadow: None,
+            sharpen: None,
+            threshold: None,
             blank_page_detection: Some(false),
         })
         .await
@@ -64,14 +78,16 @@ async fn main() {
     println!("Scanner status: \n{:#?}", status);
 
     println!("Downloading scanned page...");
-    let image = job
+    let document = job
         .next_document()
         .await
         .expect("unable to fetch scanned page")
         .expect("at least one page should be available");
 
-    let mut image_file = std::fs::File::create("./scan.jpg").expect("unable to create output file");
+    let file_name = format!("./scan.{}", document.file_extension().unwrap_or("jpg"));
+    let mut image_file = std::fs::File::create(&file_name).expect("unable to create output file");
     image_file
-        .write_all(&image)
+        .write_all(document.data())
         .expect("unable to write to output file");
+    println!("Scanned page saved to {}", file_name);
 }
@@ -0,0 +1,187 @@
+use std::{borrow::Cow, sync::Mutex};
+
+use digest_auth::{AuthContext, HttpMethod, WwwAuthenticateHeader};
+use reqwest::{
+    header::{HeaderMap, AUTHORIZATION, WWW_AUTHENTICATE},
+    Method, RequestBuilder,
+};
+use url::Url;
+
+/// Username and password for scanners that require HTTP authentication.
+///
+/// The authentication scheme is negotiated with the scanner: `Digest` is used when offered, and
+/// `Basic` otherwise. Note that `Basic` sends the password in clear text unless HTTPS is used.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    username: String,
+    password: String,
+}
+
+/// Applies [Credentials] to requests, keeping track of the scheme and challenge last sent by the
+/// scanner so that subsequent requests can be authenticated without an extra round trip.
+#[derive(Debug)]
+pub(crate) struct Authenticator {
+    credentials: Credentials,
+    scheme: Mutex<AuthScheme>,
+}
+
+#[derive(Debug, Clone)]
+enum AuthScheme {
+    /// No challenge received yet. Requests are sent without credentials.
+    Unknown,
+    Basic,
+    Digest(WwwAuthenticateHeader),
+}
+
+impl Credentials {
+    pub fn new<U, P>(username: U, password: P) -> Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        Self {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+impl Authenticator {
+    pub fn new(credentials: Credentials) -> Self {
+        Self {
+            credentials,
+            scheme: Mutex::new(AuthScheme::Unknown),
+        }
+    }
+
+    /// Adds the `Authorization` header to `request` if the scanner is known to require it.
+    pub fn authenticate(
+        &self,
+        request: RequestBuilder,
+        method: &Method,
+        url: &Url,
+        body: Option<&str>,
+    ) -> RequestBuilder {
+        let mut scheme = self.scheme.lock().unwrap_or_else(|err| err.into_inner());
+
+        match &mut *scheme {
+            AuthScheme::Unknown => request,
+            AuthScheme::Basic => {
+                request.basic_auth(&self.credentials.username, Some(&self.credentials.password))
+            }
+            AuthScheme::Digest(challenge) => {
+                let uri = match url.query() {
+                    Some(query) => format!("{}?{}", url.path(), query),
+                    None => url.path().to_owned(),
+                };
+
+                let context = AuthContext::new_with_method(
+                    self.credentials.username.as_str(),
+                    self.credentials.password.as_str(),
+                    uri,
+                    body.map(str::as_bytes),
+                    HttpMethod(Cow::Owned(method.as_str().to_owned())),
+                );
+
+                match challenge.respond(&context) {
+                    Ok(header) => request.header(AUTHORIZATION, header.to_header_string()),
+                    Err(_) => request,
+                }
+            }
+        }
+    }
+
+    /// Processes the `WWW-Authenticate` headers of a `401 Unauthorized` response. Returns whether
+    /// the request should be sent again with the updated credentials.
+    pub fn handle_challenge(&self, headers: &HeaderMap) -> bool {
+        let challenges = headers
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>();
+
+        let digest = challenges
+            .iter()
+            .filter(|challenge| has_scheme(challenge, "Digest"))
+            .find_map(|challenge| digest_auth::parse(challenge).ok());
+
+        let new_scheme = if let Some(digest) = digest {
+            AuthScheme::Digest(digest)
+        } else if challenges
+            .iter()
+            .any(|challenge| has_scheme(challenge, "Basic"))
+        {
+            AuthScheme::Basic
+        } else {
+            return false;
+        };
+
+        let mut scheme = self.scheme.lock().unwrap_or_else(|err| err.into_inner());
+
+        // Credentials already sent via `Basic` got rejected. Sending them again won't help.
+        if matches!(
+            (&*scheme, &new_scheme),
+            (AuthScheme::Basic, AuthScheme::Basic)
+        ) {
+            return false;
+        }
+
+        *scheme = new_scheme;
+        true
+    }
+}
+
+fn has_scheme(challenge: &str, scheme: &str) -> bool {
+    challenge
+        .trim_start()
+        .get(..scheme.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme))
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Client;
+
+    use super::*;
+
+    #[test]
+    pub fn test_digest_challenge() {
+        let authenticator = Authenticator::new(Credentials::new("admin", "secret"));
+        let url = Url::parse("http://192.168.1.1/eSCL/ScannerStatus").unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.append(
+            WWW_AUTHENTICATE,
+            r#"Basic realm="scanner""#.parse().unwrap(),
+        );
+        headers.append(
+            WWW_AUTHENTICATE,
+            r#"Digest realm="scanner", qop="auth", nonce="dcd98b7102dd2f0e", algorithm=MD5"#
+                .parse()
+                .unwrap(),
+        );
+        assert!(authenticator.handle_challenge(&headers));
+
+        let request = authenticator
+            .authenticate(Client::new().get(url.clone()), &Method::GET, &url, None)
+            .build()
+            .unwrap();
+        let authorization = request.headers()[AUTHORIZATION].to_str().unwrap();
+        assert!(authorization.starts_with("Digest "));
+        assert!(authorization.contains(r#"uri="/eSCL/ScannerStatus""#));
+        assert!(authorization.contains("nc=00000001"));
+    }
+}
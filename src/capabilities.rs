@@ -1,5 +1,7 @@
 use serde::{de::Visitor, Deserialize, Serialize};
 
+use crate::settings::InputSource;
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ScannerCapabilities {
@@ -16,15 +18,36 @@ pub struct ScannerCapabilities {
     pub icon_uri: String,
     #[serde(default, skip_serializing_if = "Certifications::is_empty")]
     pub certifications: Certifications,
-    pub platen: Platen,
+    /// Named profiles shared between input sources, which refer to them with
+    /// [SettingProfile::reference].
+    #[serde(default, skip_serializing_if = "SettingProfiles::is_empty")]
+    pub setting_profiles: SettingProfiles,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platen: Option<Platen>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub adf: Option<Adf>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera: Option<Camera>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub compression_factor_support: Option<CompressionFactorSupport>,
     #[serde(default, skip_serializing_if = "SupportedMediaTypes::is_empty")]
     pub supported_media_types: SupportedMediaTypes,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub sharpen_support: Option<SharpenSupport>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brightness_support: Option<BrightnessSupport>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contrast_support: Option<ContrastSupport>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gamma_support: Option<GammaSupport>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub highlight_support: Option<HighlightSupport>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noise_removal_support: Option<NoiseRemovalSupport>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shadow_support: Option<ShadowSupport>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold_support: Option<ThresholdSupport>,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -46,10 +69,39 @@ pub struct Platen {
     pub platen_input_caps: InputCaps,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Camera {
+    pub camera_input_caps: InputCaps,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Adf {
     pub adf_simplex_input_caps: InputCaps,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adf_duplex_input_caps: Option<InputCaps>,
+    /// Maximum number of sheets the feeder holds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feeder_capacity: Option<u32>,
+    #[serde(default, skip_serializing_if = "AdfOptions::is_empty")]
+    pub adf_options: AdfOptions,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub justification: Option<Justification>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AdfOptions {
+    pub adf_option: Vec<AdfOption>,
+}
+
+/// Where the feeder aligns documents narrower or shorter than its maximum size.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Justification {
+    pub x_image_position: ImagePosition,
+    pub y_image_position: ImagePosition,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -70,26 +122,40 @@ pub struct InputCaps {
     pub risky_bottom_margin: u32,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct SettingProfiles {
-    pub setting_profile: SettingProfile,
+    pub setting_profile: Vec<SettingProfile>,
 }
 
+/// A set of settings that are valid together. Any combination of a color mode, a document format
+/// and a resolution listed in the same profile is supported.
+///
+/// A profile may instead only refer to a named profile defined elsewhere in the capabilities, see
+/// [ScannerCapabilities::setting_profiles].
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct SettingProfile {
+    #[serde(rename = "name", default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Name of the profile this one stands for. All other fields are empty in that case.
+    #[serde(rename = "ref", default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    #[serde(default, skip_serializing_if = "ColorModes::is_empty")]
     pub color_modes: ColorModes,
     #[serde(default, skip_serializing_if = "ContentTypes::is_empty")]
     pub content_types: ContentTypes,
+    #[serde(default, skip_serializing_if = "DocumentFormats::is_empty")]
     pub document_formats: DocumentFormats,
+    #[serde(default, skip_serializing_if = "SupportedResolutions::is_empty")]
     pub supported_resolutions: SupportedResolutions,
+    #[serde(default, skip_serializing_if = "ColorSpaces::is_empty")]
     pub color_spaces: ColorSpaces,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub ccd_channels: Option<CcdChannels>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ColorModes {
     pub color_mode: Vec<ColorMode>,
@@ -101,33 +167,46 @@ pub struct ContentTypes {
     pub content_type: Vec<ContentType>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct DocumentFormats {
+    #[serde(default)]
     pub document_format: Vec<String>,
+    #[serde(default)]
     pub document_format_ext: Vec<String>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Resolutions supported by a setting profile, as a list of discrete values, a range, or both.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct SupportedResolutions {
+    #[serde(default, skip_serializing_if = "DiscreteResolutions::is_empty")]
     pub discrete_resolutions: DiscreteResolutions,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution_range: Option<ResolutionRange>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct DiscreteResolutions {
     pub discrete_resolution: Vec<DiscreteResolution>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ResolutionRange {
+    pub x_resolution_range: SupportRange,
+    pub y_resolution_range: SupportRange,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct DiscreteResolution {
     pub x_resolution: u32,
     pub y_resolution: u32,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ColorSpaces {
     pub color_space: Vec<String>,
@@ -145,30 +224,33 @@ pub struct SupportedIntents {
     pub intent: Vec<ScanIntent>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Range of values accepted by a numeric scan setting: `min`, `min + step`, ... up to `max`.
+/// `normal` is the value used when the setting is omitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
-pub struct CompressionFactorSupport {
+pub struct SupportRange {
     pub min: u32,
     pub max: u32,
     pub normal: u32,
     pub step: u32,
 }
 
+pub type CompressionFactorSupport = SupportRange;
+pub type SharpenSupport = SupportRange;
+pub type BrightnessSupport = SupportRange;
+pub type ContrastSupport = SupportRange;
+pub type GammaSupport = SupportRange;
+pub type HighlightSupport = SupportRange;
+pub type NoiseRemovalSupport = SupportRange;
+pub type ShadowSupport = SupportRange;
+pub type ThresholdSupport = SupportRange;
+
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct SupportedMediaTypes {
     pub media_type: Vec<String>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
-pub struct SharpenSupport {
-    pub min: u32,
-    pub max: u32,
-    pub normal: u32,
-    pub step: u32,
-}
-
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ColorMode {
     /// Binary monochrome scanning. Valid only for certain DocumentFormat/DocumentFormatExt values -
@@ -215,6 +297,26 @@ pub enum CcdChannel {
     GrayCcdEmulated,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdfOption {
+    /// The scanner can tell whether paper is loaded in the feeder.
+    DetectPaperLoaded,
+    /// The scanner can scan a single page from the feeder.
+    SelectSinglePage,
+    /// The scanner can scan both sides of each sheet.
+    Duplex,
+    Custom(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImagePosition {
+    Left,
+    Right,
+    Top,
+    Bottom,
+    Center,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ScanIntent {
     /// Scanning optimized for text.
@@ -236,6 +338,146 @@ struct ColorModeVisitor;
 struct ContentTypeVisitor;
 struct CcdChannelVisitor;
 struct ScanIntentVisitor;
+struct AdfOptionVisitor;
+struct ImagePositionVisitor;
+
+impl ScannerCapabilities {
+    /// Input sources the scanner offers, in the order platen, feeder, camera.
+    pub fn input_sources(&self) -> Vec<InputSource> {
+        [
+            InputSource::Platen,
+            InputSource::Feeder,
+            InputSource::Camera,
+        ]
+        .into_iter()
+        .filter(|source| self.input_caps(*source).is_some())
+        .collect()
+    }
+
+    /// Capabilities of the given input source, or `None` if the scanner doesn't offer it. For
+    /// [InputSource::Feeder], these are the simplex capabilities.
+    pub fn input_caps(&self, source: InputSource) -> Option<&InputCaps> {
+        match source {
+            InputSource::Platen => self.platen.as_ref().map(|platen| &platen.platen_input_caps),
+            InputSource::Feeder => self.adf.as_ref().map(|adf| &adf.adf_simplex_input_caps),
+            InputSource::Camera => self.camera.as_ref().map(|camera| &camera.camera_input_caps),
+        }
+    }
+
+    /// Setting profiles of the given input source, with references to named profiles resolved.
+    /// References that cannot be resolved are left out.
+    pub fn setting_profiles(&self, source: InputSource) -> Vec<&SettingProfile> {
+        let Some(caps) = self.input_caps(source) else {
+            return Vec::new();
+        };
+
+        caps.setting_profiles
+            .setting_profile
+            .iter()
+            .filter_map(|profile| match &profile.reference {
+                Some(reference) => self.named_setting_profile(reference),
+                None => Some(profile),
+            })
+            .collect()
+    }
+
+    /// Color modes offered by any profile of the given input source.
+    pub fn color_modes(&self, source: InputSource) -> Vec<ColorMode> {
+        let mut color_modes = Vec::new();
+        for profile in self.setting_profiles(source) {
+            for color_mode in profile.color_modes.color_mode.iter() {
+                if !color_modes.contains(color_mode) {
+                    color_modes.push(color_mode.clone());
+                }
+            }
+        }
+
+        color_modes
+    }
+
+    /// Document formats that can be combined with `color_mode` on the given input source.
+    pub fn document_formats(&self, source: InputSource, color_mode: &ColorMode) -> Vec<&str> {
+        let mut document_formats = Vec::new();
+        for profile in self.setting_profiles(source) {
+            if !profile.color_modes.color_mode.contains(color_mode) {
+                continue;
+            }
+
+            for format in profile.document_formats.formats() {
+                if !document_formats.contains(&format) {
+                    document_formats.push(format);
+                }
+            }
+        }
+
+        document_formats
+    }
+
+    /// Resolutions that can be combined with `color_mode` and `document_format` on the given input
+    /// source, one entry per matching profile.
+    pub fn supported_resolutions(
+        &self,
+        source: InputSource,
+        color_mode: &ColorMode,
+        document_format: &str,
+    ) -> Vec<&SupportedResolutions> {
+        self.setting_profiles(source)
+            .into_iter()
+            .filter(|profile| {
+                profile.color_modes.color_mode.contains(color_mode)
+                    && profile.document_formats.contains(document_format)
+            })
+            .map(|profile| &profile.supported_resolutions)
+            .collect()
+    }
+
+    /// Whether a single profile of the given input source allows `color_mode`, `document_format`
+    /// and `x_resolution` by `y_resolution` DPI together.
+    pub fn is_valid_combination(
+        &self,
+        source: InputSource,
+        color_mode: &ColorMode,
+        document_format: &str,
+        x_resolution: u32,
+        y_resolution: u32,
+    ) -> bool {
+        self.supported_resolutions(source, color_mode, document_format)
+            .into_iter()
+            .any(|resolutions| resolutions.supports(x_resolution, y_resolution))
+    }
+
+    /// Looks up a named profile, first among the shared profiles, then among those of every input
+    /// source.
+    fn named_setting_profile(&self, name: &str) -> Option<&SettingProfile> {
+        let inputs = [
+            InputSource::Platen,
+            InputSource::Feeder,
+            InputSource::Camera,
+        ]
+        .into_iter()
+        .filter_map(|source| self.input_caps(source))
+        .chain(
+            self.adf
+                .as_ref()
+                .and_then(|adf| adf.adf_duplex_input_caps.as_ref()),
+        )
+        .map(|caps| &caps.setting_profiles);
+
+        std::iter::once(&self.setting_profiles)
+            .chain(inputs)
+            .flat_map(|profiles| profiles.setting_profile.iter())
+            .find(|profile| profile.reference.is_none() && profile.name.as_deref() == Some(name))
+    }
+
+    /// Whether both sides of each sheet can be scanned from the feeder, i.e. whether
+    /// [ScanSettings::duplex](crate::settings::ScanSettings::duplex) can be enabled.
+    pub fn supports_duplex(&self) -> bool {
+        self.adf.as_ref().is_some_and(|adf| {
+            adf.adf_duplex_input_caps.is_some()
+                || adf.adf_options.adf_option.contains(&AdfOption::Duplex)
+        })
+    }
+}
 
 impl Certifications {
     fn is_empty(&self) -> bool {
@@ -243,6 +485,150 @@ impl Certifications {
     }
 }
 
+impl SupportRange {
+    /// Whether `value` is within `min..=max` and a whole number of steps away from `min`. A `step`
+    /// of 0, which some firmwares report for fixed ranges, only accepts `min` and `max`.
+    pub fn contains(&self, value: u32) -> bool {
+        if value < self.min || value > self.max {
+            return false;
+        }
+
+        match self.step {
+            0 => value == self.min || value == self.max,
+            step => (value - self.min).is_multiple_of(step),
+        }
+    }
+
+    /// Value closest to `value` that the range [contains](SupportRange::contains). Ties go to the
+    /// higher value.
+    pub fn nearest(&self, value: u32) -> u32 {
+        if self.step == 0 || self.max <= self.min {
+            return if value.abs_diff(self.min) < value.abs_diff(self.max) {
+                self.min
+            } else {
+                self.max
+            };
+        }
+
+        // `max` itself isn't necessarily on a step
+        let highest = self.min + (self.max - self.min) / self.step * self.step;
+        if value <= self.min {
+            return self.min;
+        } else if value >= highest {
+            return highest;
+        }
+
+        let lower = self.min + (value - self.min) / self.step * self.step;
+        let upper = lower + self.step;
+        if value - lower < upper - value {
+            lower
+        } else {
+            upper
+        }
+    }
+}
+
+impl SettingProfiles {
+    fn is_empty(&self) -> bool {
+        self.setting_profile.is_empty()
+    }
+}
+
+impl DocumentFormats {
+    /// All formats of the profile, from both `DocumentFormat` and `DocumentFormatExt`, without
+    /// duplicates.
+    pub fn formats(&self) -> Vec<&str> {
+        let mut formats = Vec::new();
+        for format in self
+            .document_format
+            .iter()
+            .chain(self.document_format_ext.iter())
+        {
+            if !formats.contains(&format.as_str()) {
+                formats.push(format.as_str());
+            }
+        }
+
+        formats
+    }
+
+    pub fn contains(&self, format: &str) -> bool {
+        self.document_format
+            .iter()
+            .chain(self.document_format_ext.iter())
+            .any(|candidate| candidate == format)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.document_format.is_empty() && self.document_format_ext.is_empty()
+    }
+}
+
+impl ColorSpaces {
+    fn is_empty(&self) -> bool {
+        self.color_space.is_empty()
+    }
+}
+
+impl SupportedResolutions {
+    fn is_empty(&self) -> bool {
+        self.discrete_resolutions.is_empty() && self.resolution_range.is_none()
+    }
+
+    /// Whether `x_resolution` by `y_resolution` DPI is one of the discrete resolutions, or within
+    /// the resolution range.
+    pub fn supports(&self, x_resolution: u32, y_resolution: u32) -> bool {
+        self.discrete_resolutions
+            .discrete_resolution
+            .iter()
+            .any(|resolution| {
+                resolution.x_resolution == x_resolution && resolution.y_resolution == y_resolution
+            })
+            || self.resolution_range.is_some_and(|range| {
+                range.x_resolution_range.contains(x_resolution)
+                    && range.y_resolution_range.contains(y_resolution)
+            })
+    }
+
+    /// Supported resolution closest to `x_resolution` by `y_resolution` DPI, across both discrete
+    /// resolutions and the resolution range. Ties go to the higher resolution. `None` is only
+    /// returned when no resolution is advertised at all.
+    pub fn nearest(&self, x_resolution: u32, y_resolution: u32) -> Option<DiscreteResolution> {
+        let from_range = self.resolution_range.map(|range| DiscreteResolution {
+            x_resolution: range.x_resolution_range.nearest(x_resolution),
+            y_resolution: range.y_resolution_range.nearest(y_resolution),
+        });
+
+        self.discrete_resolutions
+            .discrete_resolution
+            .iter()
+            .copied()
+            .chain(from_range)
+            .min_by_key(|resolution| {
+                let distance = u64::from(resolution.x_resolution.abs_diff(x_resolution))
+                    + u64::from(resolution.y_resolution.abs_diff(y_resolution));
+                (
+                    distance,
+                    std::cmp::Reverse(
+                        u64::from(resolution.x_resolution) + u64::from(resolution.y_resolution),
+                    ),
+                )
+            })
+    }
+}
+
+impl DiscreteResolutions {
+    fn is_empty(&self) -> bool {
+        self.discrete_resolution.is_empty()
+    }
+}
+
+impl AdfOptions {
+    fn is_empty(&self) -> bool {
+        self.adf_option.is_empty()
+    }
+}
+
 impl ContentTypes {
     fn is_empty(&self) -> bool {
         self.content_type.is_empty()
@@ -256,6 +642,10 @@ impl SupportedMediaTypes {
 }
 
 impl ColorModes {
+    fn is_empty(&self) -> bool {
+        self.color_mode.is_empty()
+    }
+
     /// Gets the highest support quality RGB color mode. If no RGB color mode is supported, `None`
     // is returned.
     pub fn color(&self) -> Option<ColorMode> {
@@ -473,6 +863,100 @@ impl<'de> Visitor<'de> for ScanIntentVisitor {
     }
 }
 
+impl Serialize for AdfOption {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(match self {
+            Self::DetectPaperLoaded => "DetectPaperLoaded",
+            Self::SelectSinglePage => "SelectSinglePage",
+            Self::Duplex => "Duplex",
+            Self::Custom(custom) => custom,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for AdfOption {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(AdfOptionVisitor)
+    }
+}
+
+impl<'de> Visitor<'de> for AdfOptionVisitor {
+    type Value = AdfOption;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "string")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(match v {
+            "DetectPaperLoaded" => AdfOption::DetectPaperLoaded,
+            "SelectSinglePage" => AdfOption::SelectSinglePage,
+            "Duplex" => AdfOption::Duplex,
+            custom => AdfOption::Custom(custom.to_owned()),
+        })
+    }
+}
+
+impl Serialize for ImagePosition {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(match self {
+            Self::Left => "Left",
+            Self::Right => "Right",
+            Self::Top => "Top",
+            Self::Bottom => "Bottom",
+            Self::Center => "Center",
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for ImagePosition {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(ImagePositionVisitor)
+    }
+}
+
+impl<'de> Visitor<'de> for ImagePositionVisitor {
+    type Value = ImagePosition;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "string")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(match v {
+            "Left" => ImagePosition::Left,
+            "Right" => ImagePosition::Right,
+            "Top" => ImagePosition::Top,
+            "Bottom" => ImagePosition::Bottom,
+            "Center" => ImagePosition::Center,
+            _ => {
+                return Err(serde::de::Error::invalid_value(
+                    serde::de::Unexpected::Str(v),
+                    &"valid ImagePosition value",
+                ))
+            }
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -483,6 +967,9 @@ mod tests {
             include_str!("../test-data/capabilities/brother_mfc_j497dw.xml"),
             include_str!("../test-data/capabilities/canon_ts5300_series.xml"),
             include_str!("../test-data/capabilities/canon_ts7450.xml"),
+            include_str!("../test-data/capabilities/duplex_adf.xml"),
+            include_str!("../test-data/capabilities/document_camera.xml"),
+            include_str!("../test-data/capabilities/setting_profiles.xml"),
         ]
         .into_iter()
         {
@@ -490,4 +977,218 @@ mod tests {
                 .expect("capabilities deserializing failure");
         }
     }
+
+    #[test]
+    pub fn test_adf_capabilities() {
+        let capabilities = serde_xml_rs::from_str::<ScannerCapabilities>(include_str!(
+            "../test-data/capabilities/brother_mfc_j497dw.xml"
+        ))
+        .expect("capabilities deserializing failure");
+        let adf = capabilities.adf.as_ref().unwrap();
+        assert_eq!(adf.feeder_capacity, Some(20));
+        assert_eq!(adf.adf_options.adf_option, [AdfOption::DetectPaperLoaded]);
+        assert!(!capabilities.supports_duplex());
+
+        let capabilities = serde_xml_rs::from_str::<ScannerCapabilities>(include_str!(
+            "../test-data/capabilities/duplex_adf.xml"
+        ))
+        .expect("capabilities deserializing failure");
+        let adf = capabilities.adf.as_ref().unwrap();
+        assert!(adf.adf_duplex_input_caps.is_some());
+        assert_eq!(
+            adf.justification,
+            Some(Justification {
+                x_image_position: ImagePosition::Center,
+                y_image_position: ImagePosition::Top,
+            })
+        );
+        assert!(capabilities.supports_duplex());
+    }
+
+    #[test]
+    pub fn test_support_ranges() {
+        let capabilities = serde_xml_rs::from_str::<ScannerCapabilities>(include_str!(
+            "../test-data/capabilities/duplex_adf.xml"
+        ))
+        .expect("capabilities deserializing failure");
+        assert_eq!(
+            capabilities.gamma_support,
+            Some(SupportRange {
+                min: 10,
+                max: 400,
+                normal: 100,
+                step: 10,
+            })
+        );
+        assert_eq!(
+            capabilities.noise_removal_support.map(|range| range.max),
+            Some(10)
+        );
+
+        let sharpen = capabilities.sharpen_support.unwrap();
+        assert!(sharpen.contains(0));
+        assert!(sharpen.contains(2));
+        assert!(!sharpen.contains(3));
+        assert!(!sharpen.contains(6));
+
+        let fixed = SupportRange {
+            min: 1,
+            max: 5,
+            normal: 1,
+            step: 0,
+        };
+        assert!(fixed.contains(5));
+        assert!(!fixed.contains(3));
+    }
+
+    #[test]
+    pub fn test_resolution_range() {
+        let capabilities = serde_xml_rs::from_str::<ScannerCapabilities>(include_str!(
+            "../test-data/capabilities/document_camera.xml"
+        ))
+        .expect("capabilities deserializing failure");
+        let resolutions = &capabilities
+            .input_caps(InputSource::Camera)
+            .unwrap()
+            .setting_profiles
+            .setting_profile[0]
+            .supported_resolutions;
+        assert!(resolutions.discrete_resolutions.is_empty());
+        assert!(resolutions.supports(300, 300));
+        assert!(resolutions.supports(75, 600));
+        assert!(!resolutions.supports(310, 310));
+        assert!(!resolutions.supports(1200, 1200));
+        assert_eq!(
+            resolutions.nearest(310, 1200),
+            Some(DiscreteResolution {
+                x_resolution: 300,
+                y_resolution: 600,
+            })
+        );
+
+        let capabilities = serde_xml_rs::from_str::<ScannerCapabilities>(include_str!(
+            "../test-data/capabilities/duplex_adf.xml"
+        ))
+        .expect("capabilities deserializing failure");
+        let resolutions = &capabilities
+            .adf
+            .unwrap()
+            .adf_duplex_input_caps
+            .unwrap()
+            .setting_profiles
+            .setting_profile[0]
+            .supported_resolutions;
+        assert!(resolutions.supports(150, 150));
+        assert!(resolutions.supports(600, 600));
+        assert!(!resolutions.supports(450, 450));
+        for (requested, expected) in [
+            (240, 250),
+            (450, 600),
+            (400, 300),
+            (50, 100),
+            (u32::MAX, 600),
+        ] {
+            assert_eq!(
+                resolutions.nearest(requested, requested),
+                Some(DiscreteResolution {
+                    x_resolution: expected,
+                    y_resolution: expected,
+                })
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_setting_profiles() {
+        let capabilities = serde_xml_rs::from_str::<ScannerCapabilities>(include_str!(
+            "../test-data/capabilities/setting_profiles.xml"
+        ))
+        .expect("capabilities deserializing failure");
+
+        let platen = capabilities.setting_profiles(InputSource::Platen);
+        assert_eq!(platen.len(), 2);
+        assert_eq!(platen[1].name.as_deref(), Some("monochrome"));
+
+        // The feeder refers to the platen's color profile
+        let feeder = capabilities.setting_profiles(InputSource::Feeder);
+        assert_eq!(feeder, [platen[0]]);
+        assert_eq!(
+            capabilities.color_modes(InputSource::Feeder),
+            [ColorMode::Grayscale8, ColorMode::RGB24]
+        );
+
+        assert_eq!(
+            capabilities.color_modes(InputSource::Platen),
+            [
+                ColorMode::Grayscale8,
+                ColorMode::RGB24,
+                ColorMode::BlackAndWhite1
+            ]
+        );
+        assert_eq!(
+            capabilities.document_formats(InputSource::Platen, &ColorMode::BlackAndWhite1),
+            ["application/pdf", "image/tiff"]
+        );
+        assert!(capabilities.is_valid_combination(
+            InputSource::Platen,
+            &ColorMode::RGB24,
+            "image/jpeg",
+            300,
+            300
+        ));
+        assert!(capabilities.is_valid_combination(
+            InputSource::Platen,
+            &ColorMode::BlackAndWhite1,
+            "application/pdf",
+            600,
+            600
+        ));
+        // Each setting is supported on its own, but not in this combination
+        assert!(!capabilities.is_valid_combination(
+            InputSource::Platen,
+            &ColorMode::RGB24,
+            "image/tiff",
+            300,
+            300
+        ));
+        assert!(!capabilities.is_valid_combination(
+            InputSource::Platen,
+            &ColorMode::RGB24,
+            "application/pdf",
+            600,
+            600
+        ));
+        assert!(!capabilities.is_valid_combination(
+            InputSource::Feeder,
+            &ColorMode::BlackAndWhite1,
+            "application/pdf",
+            300,
+            300
+        ));
+    }
+
+    #[test]
+    pub fn test_input_sources() {
+        let capabilities = serde_xml_rs::from_str::<ScannerCapabilities>(include_str!(
+            "../test-data/capabilities/brother_mfc_j497dw.xml"
+        ))
+        .expect("capabilities deserializing failure");
+        assert_eq!(
+            capabilities.input_sources(),
+            [InputSource::Platen, InputSource::Feeder]
+        );
+
+        let capabilities = serde_xml_rs::from_str::<ScannerCapabilities>(include_str!(
+            "../test-data/capabilities/document_camera.xml"
+        ))
+        .expect("capabilities deserializing failure");
+        assert_eq!(capabilities.input_sources(), [InputSource::Camera]);
+        assert_eq!(
+            capabilities
+                .input_caps(InputSource::Camera)
+                .map(|caps| caps.max_width),
+            Some(2550)
+        );
+        assert!(capabilities.input_caps(InputSource::Platen).is_none());
+    }
 }
@@ -0,0 +1,1764 @@
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap, VecDeque},
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV6},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dns_parser::QueryType;
+use futures_util::{
+    future::{self, join_all, BoxFuture, Either},
+    pin_mut,
+    stream::{self, FuturesUnordered, Stream, StreamExt},
+};
+use ipnet::IpNet;
+use url::Url;
+
+use crate::{
+    capabilities::ScannerCapabilities,
+    dns::{self, Response},
+    interface::{network_interfaces, NetworkInterface},
+    settings::InputSource,
+};
+
+pub use crate::dns::{Record, RecordKind};
+
+/// DNS-SD service type of eSCL over plain HTTP.
+const HTTP_SERVICE_TYPE: &str = "_uscan._tcp";
+
+/// DNS-SD service type of eSCL over HTTPS.
+const HTTPS_SERVICE_TYPE: &str = "_uscans._tcp";
+
+/// Domain of services advertised via mDNS.
+const MDNS_DOMAIN: &str = "local";
+
+/// Query interval used by [discover].
+const DEFAULT_QUERY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often cached records are checked for expiry in [discover_stream].
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// mDNS multicast group and port.
+const MDNS_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+
+/// How long to wait for the answer to a follow-up query.
+const FOLLOW_UP_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Domain of the host names standing for IPv6 link-local addresses in URLs. Names under `.invalid`
+/// never resolve through DNS (RFC 6761).
+const LINK_LOCAL_DOMAIN: &str = "link-local.invalid";
+
+/// Minimum delay before the same follow-up query is sent again.
+const FOLLOW_UP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Responders cap TTLs in answers to legacy unicast queries at 10 seconds (RFC 6762 section 6.7).
+/// Records obtained by follow-up queries are kept at least this long instead, so that services
+/// don't flap between added and removed.
+const FOLLOW_UP_MIN_TTL: u32 = 120;
+
+/// A TTL of zero in unicast DNS means "do not cache" rather than "goodbye" (RFC 1035 section
+/// 3.2.1). Records from wide-area answers are kept at least this long, so that they last through
+/// the lookup.
+const WIDE_AREA_MIN_TTL: u32 = 60;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannerService {
+    pub(crate) instance_name: String,
+    pub(crate) tls: bool,
+    pub(crate) addresses: Vec<SocketAddr>,
+    pub(crate) base_urls: Vec<Url>,
+    pub(crate) name: String,
+    pub(crate) txt: TxtRecord,
+    pub(crate) interface: Option<NetworkInterface>,
+}
+
+/// Metadata advertised in the TXT record of an eSCL service, as defined by the eSCL
+/// specification.
+///
+/// Keys are matched case-insensitively. All entries, including non-standard ones, are also
+/// available in [TxtRecord::raw].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TxtRecord {
+    /// Version of the TXT record format (`txtvers`)
+    pub txtvers: Option<String>,
+    /// eSCL version implemented by the scanner (`vers`)
+    pub vers: Option<String>,
+    /// Human readable make and model (`ty`)
+    pub ty: Option<String>,
+    /// Resource path of the eSCL endpoints, without leading slash (`rs`)
+    pub rs: Option<String>,
+    /// Scanner UUID (`UUID`), matching the one in
+    /// [ScannerCapabilities]
+    pub uuid: Option<String>,
+    /// Human readable location of the scanner (`note`)
+    pub note: Option<String>,
+    /// URL of the scanner's administration web page (`adminurl`)
+    pub admin_url: Option<Url>,
+    /// URL of the scanner icon (`representation`)
+    pub representation: Option<Url>,
+    /// Supported color spaces, e.g. `color`, `grayscale` and `binary` (`cs`)
+    pub color_spaces: Vec<String>,
+    /// Supported document format MIME types (`pdl`)
+    pub document_formats: Vec<String>,
+    /// Supported input sources, e.g. `platen`, `adf` and `camera` (`is`)
+    pub input_sources: Vec<String>,
+    /// Whether duplex scanning is supported (`duplex`)
+    pub duplex: Option<bool>,
+    /// All entries keyed by lowercase key. Keys present without a value map to an empty string.
+    pub raw: BTreeMap<String, String>,
+}
+
+/// Network interfaces to run mDNS discovery on, as used by [discover_on] and
+/// [discover_stream_on]. Only [Interfaces::Default] is supported outside Linux, where responses
+/// cannot be told apart by the interface they arrived on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Interfaces {
+    /// The interface picked by the operating system for multicast traffic.
+    #[default]
+    Default,
+    /// All interfaces listed by [network_interfaces], concurrently.
+    All,
+    /// The given interfaces, concurrently.
+    Selected(Vec<InterfaceSelector>),
+}
+
+/// Identifies a network interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterfaceSelector {
+    /// Interface name (e.g. `eth0`), as listed by [network_interfaces].
+    Name(String),
+    /// IPv4 address of the host on the interface, to be used as source address of queries.
+    Address(Ipv4Addr),
+}
+
+#[derive(Debug)]
+pub enum DiscoverError {
+    /// Sending or receiving mDNS packets failed.
+    Io(std::io::Error),
+    /// Sending a unicast DNS query or receiving its response failed.
+    Dns(std::io::Error),
+    /// Network interfaces cannot be listed, or a selected interface does not exist.
+    Interface(std::io::Error),
+    /// The HTTP client used by [Sweep](crate::Sweep) cannot be built.
+    Http(reqwest::Error),
+    /// The network passed to [Sweep](crate::Sweep) has too many addresses to be probed.
+    NetworkTooLarge(IpNet),
+}
+
+/// Outcome of a diagnostic discovery run with [discover_diagnostics].
+#[derive(Debug, Clone)]
+pub struct DiscoveryReport {
+    /// One entry for every service instance announced via a PTR record
+    pub services: Vec<ServiceDiagnostics>,
+    /// All records received, in the order they were received, including those unrelated to any
+    /// service instance
+    pub records: Vec<Record>,
+}
+
+/// Resolution result for a single service instance announced via a PTR record.
+#[derive(Debug, Clone)]
+pub struct ServiceDiagnostics {
+    /// DNS-SD service instance name the PTR record points to
+    pub instance_name: String,
+    /// Whether the instance is of the `_uscans._tcp` service type
+    pub tls: bool,
+    /// The resolved service, or why it cannot be used
+    pub result: Result<ScannerService, RejectionReason>,
+    /// Records involved in resolving the instance: the PTR record itself, records on the instance
+    /// name, and records on the host name targeted by SRV records
+    pub records: Vec<Record>,
+}
+
+/// Why an announced service instance cannot be turned into a [ScannerService].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectionReason {
+    /// No TXT record was received for the instance.
+    MissingTxtRecord,
+    /// The TXT record lacks the `rs` key with the eSCL resource path.
+    MissingResourcePath,
+    /// The TXT record lacks the `ty` key with the make and model.
+    MissingName,
+    /// No SRV record was received for the instance.
+    MissingSrvRecord,
+    /// No A or AAAA record was received for the host name targeted by the SRV record.
+    MissingAddress { target: String },
+    /// The host name targeted by the SRV record only has IPv6 link-local addresses, which cannot
+    /// be used without knowing the interface they were received on.
+    OnlyLinkLocalAddresses { target: String },
+}
+
+/// A change in the set of scanners visible on the network, as yielded by [discover_stream].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiscoveryEvent {
+    /// A scanner has been discovered for the first time.
+    Added(ScannerService),
+    /// A previously discovered scanner has changed some of its advertised details (e.g. its
+    /// address).
+    Updated(ScannerService),
+    /// A scanner has left the network, either by announcing so or by letting its records expire.
+    /// The last known details of the scanner are provided.
+    Removed(ScannerService),
+}
+
+/// DNS records received so far, each kept until its TTL runs out.
+#[derive(Debug, Default)]
+struct RecordCache {
+    records: Vec<CachedRecord>,
+}
+
+#[derive(Debug)]
+struct CachedRecord {
+    record: Record,
+    expires_at: Instant,
+}
+
+/// Aggregates DNS responses into a live set of scanner services.
+#[derive(Debug)]
+struct Browser {
+    /// Service names browsed, secure one first, along with whether they're secure
+    service_names: [(String, bool); 2],
+    /// Interface the responses are received on, if known
+    interface: Option<NetworkInterface>,
+    cache: RecordCache,
+    services: HashMap<String, ScannerService>,
+    /// Follow-up queries sent recently, with the time they were sent
+    follow_ups: HashMap<FollowUp, Instant>,
+}
+
+/// A query for records missing to resolve a service, for devices that don't include them in the
+/// additional section of their PTR answers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum FollowUp {
+    /// SRV and TXT records of a service instance
+    Instance(String),
+    /// A and AAAA records of the host targeted by an SRV record
+    Host(String),
+}
+
+enum BrowseInput {
+    /// A response received by the browser at the given index
+    Response(usize, std::io::Result<Response>),
+    /// Answers to the follow-up queries of the browser at the given index
+    FollowUps(usize, Vec<Response>),
+    Tick,
+}
+
+impl ScannerService {
+    /// Base URL that can be used to initialize a [Scanner](crate::Scanner) instance. This is the
+    /// first of [ScannerService::urls].
+    pub fn url(&self) -> &Url {
+        &self.base_urls[0]
+    }
+
+    /// Base URLs for all usable addresses of the scanner. `https://` URLs come first when the
+    /// scanner is also advertised via `_uscans._tcp`, then IPv4 before IPv6.
+    ///
+    /// URLs cannot carry the zone of IPv6 link-local addresses, which are represented by host
+    /// names such as `fe80--1s2.link-local.invalid` for `fe80::1%2` instead. These only resolve
+    /// with an HTTP client set up for the service, as done by the [Scanner](crate::Scanner)
+    /// conversion and [ScannerBuilder::from_service](crate::ScannerBuilder::from_service).
+    ///
+    /// A [Scanner](crate::Scanner) created from the service tries the plain HTTP URLs in this
+    /// order, while [ScannerBuilder::from_service](crate::ScannerBuilder::from_service) tries the
+    /// HTTPS ones. Neither mixes both schemes.
+    pub fn urls(&self) -> &[Url] {
+        &self.base_urls
+    }
+
+    /// All addresses advertised for the scanner, IPv4 first.
+    ///
+    /// IPv6 link-local addresses are only usable together with the zone (interface) they were
+    /// received on. Their zone is set when the [ScannerService::interface] is known, and they're
+    /// left out of [ScannerService::urls] otherwise.
+    pub fn addresses(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+
+    /// Human readable scanner make and model
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// DNS-SD service instance name (e.g. `Brother MFC-J497DW._uscan._tcp.local`), which uniquely
+    /// identifies the scanner on the network.
+    ///
+    /// For scanners advertised via both `_uscan._tcp` and `_uscans._tcp`, this is the name of the
+    /// `_uscans._tcp` instance. For scanners found by a [Sweep](crate::sweep::Sweep), which
+    /// doesn't involve DNS-SD, this is the base URL.
+    pub fn instance_name(&self) -> &str {
+        &self.instance_name
+    }
+
+    /// Whether the scanner is advertised via `_uscans._tcp`, i.e. can be reached over HTTPS.
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
+
+    /// Metadata from the TXT record of the service
+    pub fn txt(&self) -> &TxtRecord {
+        &self.txt
+    }
+
+    /// Network interface the service was discovered on. This is only known when discovery runs
+    /// on explicitly selected interfaces, see [Interfaces].
+    pub fn interface(&self) -> Option<&NetworkInterface> {
+        self.interface.as_ref()
+    }
+}
+
+impl ScannerService {
+    /// Builds a service for a scanner found without DNS-SD, at `base_url`. The TXT record is
+    /// synthesized from the `capabilities` document, and the base URL doubles as instance name.
+    pub(crate) fn from_capabilities(
+        base_url: Url,
+        address: SocketAddr,
+        capabilities: &ScannerCapabilities,
+    ) -> Self {
+        let input_sources = capabilities
+            .input_sources()
+            .into_iter()
+            .map(|source| {
+                match source {
+                    InputSource::Platen => "platen",
+                    InputSource::Feeder => "adf",
+                    InputSource::Camera => "camera",
+                }
+                .to_owned()
+            })
+            .collect::<Vec<_>>();
+
+        let mut document_formats = Vec::new();
+        for source in capabilities.input_sources() {
+            for profile in capabilities.setting_profiles(source) {
+                for format in profile.document_formats.formats() {
+                    if !document_formats.iter().any(|known| known == format) {
+                        document_formats.push(format.to_owned());
+                    }
+                }
+            }
+        }
+
+        let txt = TxtRecord {
+            vers: Some(capabilities.version.clone()),
+            ty: Some(capabilities.make_and_model.clone()),
+            rs: Some(base_url.path().trim_matches('/').to_owned()),
+            uuid: Some(capabilities.uuid.clone()),
+            admin_url: Url::parse(&capabilities.admin_uri).ok(),
+            representation: Url::parse(&capabilities.icon_uri).ok(),
+            document_formats,
+            input_sources,
+            ..Default::default()
+        };
+
+        Self {
+            instance_name: base_url.to_string(),
+            tls: base_url.scheme() == "https",
+            addresses: vec![address],
+            base_urls: vec![base_url],
+            name: capabilities.make_and_model.clone(),
+            txt,
+            interface: None,
+        }
+    }
+
+    /// Host names used in [ScannerService::urls] for IPv6 link-local addresses, along with the
+    /// address each stands for.
+    pub(crate) fn link_local_hosts(&self) -> Vec<(String, SocketAddr)> {
+        self.addresses
+            .iter()
+            .filter_map(|address| match address {
+                SocketAddr::V6(address)
+                    if is_link_local(&SocketAddr::V6(*address)) && address.scope_id() != 0 =>
+                {
+                    Some((link_local_host(address), SocketAddr::V6(*address)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Key identifying the device behind the service: the UUID if advertised, so that the
+    /// `_uscan._tcp` and `_uscans._tcp` advertisements of the same scanner are merged.
+    pub(crate) fn device_key(&self) -> &str {
+        self.txt.uuid.as_deref().unwrap_or(&self.instance_name)
+    }
+
+    /// Merges in another advertisement of the same device, whose addresses and URLs are ranked
+    /// after the existing ones.
+    pub(crate) fn merge(&mut self, other: ScannerService) {
+        for address in other.addresses {
+            if !self.addresses.contains(&address) {
+                self.addresses.push(address);
+            }
+        }
+        self.base_urls.extend(other.base_urls);
+    }
+}
+
+impl TxtRecord {
+    /// Parses TXT record entries in the `key=value` form.
+    pub fn parse<S>(entries: &[S]) -> Self
+    where
+        S: AsRef<str>,
+    {
+        let raw = entries
+            .iter()
+            .filter_map(|entry| {
+                let entry = entry.as_ref();
+                let (key, value) = entry.split_once('=').unwrap_or((entry, ""));
+
+                if key.is_empty() {
+                    None
+                } else {
+                    Some((key.to_ascii_lowercase(), value.to_owned()))
+                }
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let string = |key: &str| raw.get(key).filter(|value| !value.is_empty()).cloned();
+        let url = |key: &str| raw.get(key).and_then(|value| Url::parse(value).ok());
+        let list = |key: &str| {
+            raw.get(key)
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        Self {
+            txtvers: string("txtvers"),
+            vers: string("vers"),
+            ty: string("ty"),
+            rs: raw
+                .get("rs")
+                .map(|value| value.trim_start_matches('/').to_owned()),
+            uuid: string("uuid"),
+            note: string("note"),
+            admin_url: url("adminurl"),
+            representation: url("representation"),
+            color_spaces: list("cs"),
+            document_formats: list("pdl"),
+            input_sources: list("is"),
+            duplex: raw.get("duplex").map(|value| {
+                // A key present without value is a boolean flag
+                value.is_empty() || value.eq_ignore_ascii_case("T") || value == "1"
+            }),
+            raw,
+        }
+    }
+}
+
+impl Interfaces {
+    /// Resolves the interfaces to run discovery on, `None` standing for the default one.
+    fn resolve(&self) -> Result<Vec<Option<NetworkInterface>>, DiscoverError> {
+        #[cfg(not(target_os = "linux"))]
+        if *self != Self::Default {
+            return Err(DiscoverError::Interface(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "browsing specific network interfaces is only supported on Linux",
+            )));
+        }
+
+        match self {
+            Self::Default => Ok(vec![None]),
+            Self::All => Ok(network_interfaces()
+                .map_err(DiscoverError::Interface)?
+                .into_iter()
+                .map(Some)
+                .collect()),
+            Self::Selected(selectors) => {
+                // Addresses can be used even where interfaces can't be listed
+                let available = network_interfaces().unwrap_or_default();
+
+                selectors
+                    .iter()
+                    .map(|selector| {
+                        let found = available.iter().find(|interface| match selector {
+                            InterfaceSelector::Name(name) => &interface.name == name,
+                            InterfaceSelector::Address(address) => interface.address == *address,
+                        });
+
+                        match (found, selector) {
+                            (Some(interface), _) => Ok(Some(interface.clone())),
+                            (None, InterfaceSelector::Address(address)) => {
+                                Ok(Some(NetworkInterface::from_address(*address)))
+                            }
+                            (None, InterfaceSelector::Name(name)) => {
+                                Err(DiscoverError::Interface(std::io::Error::new(
+                                    std::io::ErrorKind::NotFound,
+                                    format!("network interface {} not found", name),
+                                )))
+                            }
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+impl Display for DiscoverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "mDNS I/O error: {}", err),
+            Self::Dns(err) => write!(f, "DNS error: {}", err),
+            Self::Interface(err) => write!(f, "network interface error: {}", err),
+            Self::Http(err) => write!(f, "HTTP error: {}", err),
+            Self::NetworkTooLarge(network) => write!(f, "network too large to sweep: {}", network),
+        }
+    }
+}
+
+impl std::error::Error for DiscoverError {}
+
+impl Display for RejectionReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingTxtRecord => write!(f, "no TXT record"),
+            Self::MissingResourcePath => write!(f, "TXT record without `rs` key"),
+            Self::MissingName => write!(f, "TXT record without `ty` key"),
+            Self::MissingSrvRecord => write!(f, "no SRV record"),
+            Self::MissingAddress { target } => write!(f, "no A or AAAA record for {}", target),
+            Self::OnlyLinkLocalAddresses { target } => {
+                write!(f, "only IPv6 link-local addresses for {}", target)
+            }
+        }
+    }
+}
+
+impl RecordCache {
+    /// Adds or refreshes a record. A TTL of zero is a "goodbye" announcement which removes the
+    /// record right away.
+    fn insert(&mut self, record: &Record, now: Instant) {
+        self.records.retain(|cached| {
+            cached.record.name != record.name || cached.record.kind != record.kind
+        });
+
+        if record.ttl > 0 {
+            self.records.push(CachedRecord {
+                record: record.clone(),
+                expires_at: now + Duration::from_secs(record.ttl.into()),
+            });
+        }
+    }
+
+    /// Drops expired records. Returns whether any record was dropped.
+    fn expire(&mut self, now: Instant) -> bool {
+        let len_before = self.records.len();
+        self.records.retain(|cached| cached.expires_at > now);
+        self.records.len() != len_before
+    }
+
+    fn records(&self) -> impl Iterator<Item = &Record> {
+        self.records.iter().map(|cached| &cached.record)
+    }
+
+    fn named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a RecordKind> {
+        self.records()
+            .filter(move |record| record.name == name)
+            .map(|record| &record.kind)
+    }
+}
+
+impl Browser {
+    /// Creates a browser for the eSCL services of `domain`.
+    fn new(domain: &str) -> Self {
+        Self {
+            service_names: [
+                (format!("{}.{}", HTTPS_SERVICE_TYPE, domain), true),
+                (format!("{}.{}", HTTP_SERVICE_TYPE, domain), false),
+            ],
+            interface: None,
+            cache: RecordCache::default(),
+            services: HashMap::new(),
+            follow_ups: HashMap::new(),
+        }
+    }
+
+    /// Creates a browser for mDNS responses received on `interface`.
+    fn on_interface(interface: Option<NetworkInterface>) -> Self {
+        Self {
+            interface,
+            ..Self::default()
+        }
+    }
+
+    /// Resolves a service instance, tagging it with the interface of the browser, which also
+    /// provides the zone of IPv6 link-local addresses.
+    fn resolve(&self, instance_name: &str, tls: bool) -> Result<ScannerService, RejectionReason> {
+        let mut service = resolve_service(&self.cache, instance_name, tls, self.scope_id())?;
+        service.interface = self.interface.clone();
+        Ok(service)
+    }
+
+    /// Zone of IPv6 link-local addresses received by the browser, `0` if unknown.
+    fn scope_id(&self) -> u32 {
+        self.interface
+            .as_ref()
+            .map_or(0, |interface| interface.index)
+    }
+
+    /// Services known after the last refresh.
+    fn services(&self) -> impl Iterator<Item = &ScannerService> {
+        self.services.values()
+    }
+
+    fn handle_response(&mut self, response: &Response, now: Instant) -> Vec<DiscoveryEvent> {
+        for record in response.records() {
+            self.cache.insert(record, now);
+        }
+
+        self.refresh()
+    }
+
+    /// Handles the answer to a follow-up query.
+    fn handle_follow_up_response(
+        &mut self,
+        response: &Response,
+        now: Instant,
+    ) -> Vec<DiscoveryEvent> {
+        let mut response = response.clone();
+        for record in response.records_mut() {
+            if record.ttl > 0 {
+                record.ttl = record.ttl.max(FOLLOW_UP_MIN_TTL);
+            }
+        }
+
+        self.handle_response(&response, now)
+    }
+
+    /// Handles the answer to a wide-area DNS query.
+    fn handle_wide_area_response(
+        &mut self,
+        response: &Response,
+        now: Instant,
+    ) -> Vec<DiscoveryEvent> {
+        let mut response = response.clone();
+        for record in response.records_mut() {
+            record.ttl = record.ttl.max(WIDE_AREA_MIN_TTL);
+        }
+
+        self.handle_response(&response, now)
+    }
+
+    /// Determines the follow-up queries needed to resolve the service instances that are missing
+    /// records, leaving out the ones sent within [FOLLOW_UP_INTERVAL].
+    fn follow_ups(&mut self, now: Instant) -> Vec<FollowUp> {
+        self.follow_ups
+            .retain(|_, sent_at| now.duration_since(*sent_at) < FOLLOW_UP_INTERVAL);
+
+        let mut follow_ups = vec![];
+
+        for (service_name, tls) in self.service_names.iter() {
+            for kind in self.cache.named(service_name) {
+                let instance_name = match kind {
+                    RecordKind::PTR(instance_name) => instance_name,
+                    _ => continue,
+                };
+
+                let follow_up =
+                    match resolve_service(&self.cache, instance_name, *tls, self.scope_id()) {
+                        Err(
+                            RejectionReason::MissingTxtRecord | RejectionReason::MissingSrvRecord,
+                        ) => FollowUp::Instance(instance_name.to_owned()),
+                        Err(RejectionReason::MissingAddress { target }) => FollowUp::Host(target),
+                        _ => continue,
+                    };
+
+                if !self.follow_ups.contains_key(&follow_up) && !follow_ups.contains(&follow_up) {
+                    follow_ups.push(follow_up);
+                }
+            }
+        }
+
+        for follow_up in follow_ups.iter() {
+            self.follow_ups.insert(follow_up.clone(), now);
+        }
+
+        follow_ups
+    }
+
+    fn expire(&mut self, now: Instant) -> Vec<DiscoveryEvent> {
+        if self.cache.expire(now) {
+            self.refresh()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Resolves services from the cache and diffs them against the known ones.
+    fn refresh(&mut self) -> Vec<DiscoveryEvent> {
+        let mut resolved: HashMap<String, ScannerService> = HashMap::new();
+
+        // Secure services go first so that they take precedence when merging
+        for (service_name, tls) in self.service_names.iter() {
+            let services = self
+                .cache
+                .named(service_name)
+                .filter_map(|kind| match kind {
+                    RecordKind::PTR(instance_name) => self.resolve(instance_name, *tls).ok(),
+                    _ => None,
+                });
+
+            for service in services {
+                match resolved.entry(service.device_key().to_owned()) {
+                    Entry::Occupied(mut entry) => entry.get_mut().merge(service),
+                    Entry::Vacant(entry) => {
+                        entry.insert(service);
+                    }
+                }
+            }
+        }
+
+        let mut events = vec![];
+
+        for (key, service) in resolved.iter() {
+            match self.services.get(key) {
+                None => events.push(DiscoveryEvent::Added(service.clone())),
+                Some(known) if known != service => {
+                    events.push(DiscoveryEvent::Updated(service.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+
+        for (key, service) in self.services.iter() {
+            if !resolved.contains_key(key) {
+                events.push(DiscoveryEvent::Removed(service.clone()));
+            }
+        }
+
+        self.services = resolved;
+        events
+    }
+
+    /// Resolves every PTR record in the cache individually, without merging, keeping track of
+    /// why resolution failed.
+    fn diagnose(&self) -> Vec<ServiceDiagnostics> {
+        let mut services = vec![];
+
+        for (service_name, tls) in self.service_names.iter() {
+            for ptr_record in self.cache.records() {
+                let instance_name = match &ptr_record.kind {
+                    RecordKind::PTR(instance_name) if ptr_record.name == *service_name => {
+                        instance_name
+                    }
+                    _ => continue,
+                };
+
+                let targets = self
+                    .cache
+                    .named(instance_name)
+                    .filter_map(|kind| match kind {
+                        RecordKind::SRV { target, .. } => Some(target.as_str()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+
+                let records = std::iter::once(ptr_record)
+                    .chain(self.cache.records().filter(|record| {
+                        &record.name == instance_name || targets.contains(&record.name.as_str())
+                    }))
+                    .cloned()
+                    .collect();
+
+                services.push(ServiceDiagnostics {
+                    instance_name: instance_name.to_owned(),
+                    tls: *tls,
+                    result: self.resolve(instance_name, *tls),
+                    records,
+                });
+            }
+        }
+
+        services
+    }
+}
+
+impl Default for Browser {
+    fn default() -> Self {
+        Self::new(MDNS_DOMAIN)
+    }
+}
+
+impl FollowUp {
+    fn questions(&self) -> Vec<(&str, QueryType)> {
+        match self {
+            Self::Instance(instance_name) => vec![
+                (instance_name.as_str(), QueryType::SRV),
+                (instance_name.as_str(), QueryType::TXT),
+            ],
+            Self::Host(host_name) => vec![
+                (host_name.as_str(), QueryType::A),
+                (host_name.as_str(), QueryType::AAAA),
+            ],
+        }
+    }
+}
+
+/// Looks for eSCL-enabled scanner devices in LAN. Up to a set timeout.
+///
+/// All responses received within `timeout` are aggregated, so scanners answering late are also
+/// included.
+pub async fn discover(timeout: Duration) -> Result<Vec<ScannerService>, DiscoverError> {
+    discover_on(&Interfaces::Default, timeout).await
+}
+
+/// Looks for eSCL-enabled scanner devices in LAN on the selected network `interfaces`, up to a
+/// set timeout. A scanner reachable through several interfaces is reported once per interface.
+pub async fn discover_on(
+    interfaces: &Interfaces,
+    timeout: Duration,
+) -> Result<Vec<ScannerService>, DiscoverError> {
+    let events = discover_stream_on(interfaces, DEFAULT_QUERY_INTERVAL.min(timeout))?
+        .take_until(tokio::time::sleep(timeout));
+    pin_mut!(events);
+
+    let mut services: Vec<ScannerService> = vec![];
+    while let Some(event) = events.next().await {
+        match event? {
+            DiscoveryEvent::Added(service) => services.push(service),
+            DiscoveryEvent::Updated(service) => {
+                for known in services.iter_mut() {
+                    if known.device_key() == service.device_key()
+                        && known.interface == service.interface
+                    {
+                        *known = service.clone();
+                    }
+                }
+            }
+            DiscoveryEvent::Removed(service) => services.retain(|known| {
+                known.device_key() != service.device_key() || known.interface != service.interface
+            }),
+        }
+    }
+
+    Ok(services)
+}
+
+/// Continuously looks for eSCL-enabled scanner devices in LAN, sending a query every
+/// `query_interval`. Both the `_uscan._tcp` (HTTP) and `_uscans._tcp` (HTTPS) service types are
+/// browsed.
+///
+/// The returned stream never ends on its own. It yields a [DiscoveryEvent] whenever a scanner
+/// appears, changes, or disappears, taking record TTLs and goodbye announcements into account.
+///
+/// Scanners answering without SRV, TXT or address records are queried again for the missing
+/// records, which are then used to complete the [ScannerService].
+pub fn discover_stream(
+    query_interval: Duration,
+) -> Result<impl Stream<Item = Result<DiscoveryEvent, DiscoverError>>, DiscoverError> {
+    discover_stream_on(&Interfaces::Default, query_interval)
+}
+
+/// Continuously looks for eSCL-enabled scanner devices in LAN like [discover_stream], on the
+/// selected network `interfaces`. Each interface is browsed independently, so a scanner reachable
+/// through several interfaces is reported once per interface.
+///
+/// Interfaces that fail are skipped. An error is only returned if none can be browsed, and only
+/// yielded once all of them have failed since they last received a response.
+pub fn discover_stream_on(
+    interfaces: &Interfaces,
+    query_interval: Duration,
+) -> Result<impl Stream<Item = Result<DiscoveryEvent, DiscoverError>>, DiscoverError> {
+    let mut bound = vec![];
+    let mut responses = vec![];
+    let mut last_err = None;
+    for interface in interfaces.resolve()? {
+        match mdns_responses(query_interval, interface.as_ref()) {
+            Ok(interface_responses) => {
+                let ind = bound.len();
+                responses.push(
+                    interface_responses.map(move |response| BrowseInput::Response(ind, response)),
+                );
+                bound.push(interface);
+            }
+            Err(err) => last_err = Some(err),
+        }
+    }
+    match last_err {
+        Some(err) if bound.is_empty() => return Err(err),
+        _ => {}
+    }
+    let responses = stream::select_all(responses);
+
+    let failed = vec![false; bound.len()];
+    let browsers = bound
+        .into_iter()
+        .map(Browser::on_interface)
+        .collect::<Vec<_>>();
+
+    let ticks = stream::unfold((), |_| async {
+        tokio::time::sleep(EXPIRY_CHECK_INTERVAL).await;
+        Some((BrowseInput::Tick, ()))
+    });
+    let inputs = Box::pin(stream::select(responses, ticks));
+
+    // Follow-up queries wait for their answers aside, so that other inputs keep being handled
+    let follow_ups: FuturesUnordered<BoxFuture<'static, BrowseInput>> = FuturesUnordered::new();
+
+    Ok(stream::unfold(
+        (inputs, follow_ups, browsers, failed, VecDeque::new()),
+        |(mut inputs, mut follow_ups, mut browsers, mut failed, mut pending)| async move {
+            loop {
+                if let Some(event) = pending.pop_front() {
+                    return Some((Ok(event), (inputs, follow_ups, browsers, failed, pending)));
+                }
+
+                let input = if follow_ups.is_empty() {
+                    inputs.next().await?
+                } else {
+                    match future::select(inputs.next(), follow_ups.next()).await {
+                        Either::Left((input, _)) => input?,
+                        Either::Right((input, _)) => input.expect("No follow-up pending"),
+                    }
+                };
+
+                match input {
+                    BrowseInput::Response(ind, Ok(response)) => {
+                        failed[ind] = false;
+                        let browser = &mut browsers[ind];
+                        pending.extend(browser.handle_response(&response, Instant::now()));
+
+                        let queries = browser.follow_ups(Instant::now());
+                        if !queries.is_empty() {
+                            let interface_addr = browser
+                                .interface
+                                .as_ref()
+                                .map(|interface| interface.address);
+                            follow_ups.push(Box::pin(async move {
+                                BrowseInput::FollowUps(
+                                    ind,
+                                    send_follow_ups(&queries, interface_addr).await,
+                                )
+                            }));
+                        }
+                    }
+                    BrowseInput::Response(ind, Err(err)) => {
+                        failed[ind] = true;
+                        if failed.iter().all(|failed| *failed) {
+                            failed.fill(false);
+                            return Some((
+                                Err(DiscoverError::Io(err)),
+                                (inputs, follow_ups, browsers, failed, pending),
+                            ));
+                        }
+                    }
+                    BrowseInput::FollowUps(ind, responses) => {
+                        for response in responses {
+                            pending.extend(
+                                browsers[ind].handle_follow_up_response(&response, Instant::now()),
+                            );
+                        }
+                    }
+                    BrowseInput::Tick => {
+                        for browser in browsers.iter_mut() {
+                            pending.extend(browser.expire(Instant::now()));
+                        }
+                    }
+                }
+            }
+        },
+    ))
+}
+
+/// Looks for eSCL-enabled scanner devices in LAN like [discover], but reports on every service
+/// instance seen instead of only the usable ones. This is meant for troubleshooting scanners that
+/// don't show up.
+pub async fn discover_diagnostics(timeout: Duration) -> Result<DiscoveryReport, DiscoverError> {
+    let responses = mdns_responses(DEFAULT_QUERY_INTERVAL.min(timeout), None)?
+        .take_until(tokio::time::sleep(timeout));
+    pin_mut!(responses);
+
+    let mut browser = Browser::default();
+    let mut records = vec![];
+    while let Some(response) = responses.next().await {
+        let response = response.map_err(DiscoverError::Io)?;
+
+        records.extend(response.records().cloned());
+        browser.handle_response(&response, Instant::now());
+
+        let follow_ups = browser.follow_ups(Instant::now());
+        for response in send_follow_ups(&follow_ups, None).await {
+            records.extend(response.records().cloned());
+            browser.handle_follow_up_response(&response, Instant::now());
+        }
+    }
+
+    Ok(DiscoveryReport {
+        services: browser.diagnose(),
+        records,
+    })
+}
+
+/// Looks for eSCL-enabled scanners published in `domain` via wide-area DNS-SD, i.e. as regular
+/// DNS records, by querying the DNS server at `server`.
+///
+/// PTR records of both the `_uscan._tcp` and `_uscans._tcp` service types are looked up first,
+/// followed by the SRV, TXT, A and AAAA records needed to resolve each service. Queries that fail
+/// or aren't answered within `timeout` are treated as having no records. An error is only returned
+/// if no query gets answered at all and some failed. Responses truncated over UDP are queried
+/// again over TCP.
+///
+/// ```no_run
+/// # async fn run() {
+/// use std::time::Duration;
+///
+/// let services = escl::discover_wide_area(
+///     "branch.example.com",
+///     "192.168.1.53:53".parse().unwrap(),
+///     Duration::from_secs(2),
+/// )
+/// .await
+/// .unwrap();
+/// # }
+/// ```
+pub async fn discover_wide_area(
+    domain: &str,
+    server: SocketAddr,
+    timeout: Duration,
+) -> Result<Vec<ScannerService>, DiscoverError> {
+    let domain = domain.trim_end_matches('.');
+    let mut browser = Browser::new(domain);
+
+    let mut questions = browser
+        .service_names
+        .iter()
+        .map(|(service_name, _)| (service_name.clone(), QueryType::PTR))
+        .collect::<Vec<_>>();
+
+    // All rounds share the same time so that each follow-up query is only sent once
+    let now = Instant::now();
+
+    let mut answered = false;
+    let mut last_err = None;
+
+    // Each round of answers might point to records not received yet
+    while !questions.is_empty() {
+        // Unicast DNS servers generally only support one question per query
+        let responses = join_all(questions.iter().map(|(name, query_type)| async move {
+            dns::query(server, &[(name.as_str(), *query_type)], true, None, timeout).await
+        }))
+        .await;
+
+        // A failed query, e.g. after an ICMP error, doesn't prevent others from succeeding
+        for response in responses {
+            match response {
+                Ok(Some(response)) => {
+                    answered = true;
+                    browser.handle_wide_area_response(&response, now);
+                }
+                Ok(None) => {}
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        questions = browser
+            .follow_ups(now)
+            .iter()
+            .flat_map(|follow_up| {
+                follow_up
+                    .questions()
+                    .into_iter()
+                    .map(|(name, query_type)| (name.to_owned(), query_type))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+    }
+
+    match last_err {
+        Some(err) if !answered => Err(DiscoverError::Dns(err)),
+        _ => Ok(browser.services().cloned().collect()),
+    }
+}
+
+/// Sends queries for both eSCL service types every `query_interval` on `interface`, yielding the
+/// responses received on it. `None` leaves the choice of the interface to the operating system.
+fn mdns_responses(
+    query_interval: Duration,
+    interface: Option<&NetworkInterface>,
+) -> Result<impl Stream<Item = std::io::Result<Response>>, DiscoverError> {
+    let socket = Arc::new(dns::MdnsSocket::bind(interface).map_err(DiscoverError::Io)?);
+
+    let service_names = [HTTP_SERVICE_TYPE, HTTPS_SERVICE_TYPE]
+        .map(|service_type| format!("{}.{}", service_type, MDNS_DOMAIN));
+    let questions = service_names
+        .iter()
+        .map(|service_name| (service_name.as_str(), QueryType::PTR))
+        .collect::<Vec<_>>();
+    let query = dns::build_query(0, &questions, false).map_err(DiscoverError::Io)?;
+
+    // Only failures to send are yielded
+    let queries = stream::unfold(
+        (socket.clone(), query, true),
+        move |(socket, query, first)| async move {
+            if !first {
+                tokio::time::sleep(query_interval).await;
+            }
+            let result = socket.send(&query).await;
+            Some((result, (socket, query, false)))
+        },
+    )
+    .filter_map(|result| async move { result.err().map(Err) });
+
+    let responses = stream::unfold(socket, |socket| async move {
+        let response = socket.recv().await;
+        Some((response, socket))
+    });
+
+    Ok(stream::select(Box::pin(queries), Box::pin(responses)))
+}
+
+/// Sends follow-up queries as legacy unicast mDNS queries, concurrently, on the interface with
+/// address `interface_addr` if set. Failed or unanswered queries are skipped, as they're retried
+/// later anyway.
+async fn send_follow_ups(
+    follow_ups: &[FollowUp],
+    interface_addr: Option<Ipv4Addr>,
+) -> Vec<Response> {
+    join_all(follow_ups.iter().map(|follow_up| async move {
+        dns::query(
+            MDNS_ADDR,
+            &follow_up.questions(),
+            false,
+            interface_addr,
+            FOLLOW_UP_TIMEOUT,
+        )
+        .await
+    }))
+    .await
+    .into_iter()
+    .filter_map(|response| response.ok().flatten())
+    .collect()
+}
+
+/// Resolves the service instance named `instance_name` from cached records. Each PTR record on
+/// the service name represents one scanner. `tls` tells whether the instance is of the
+/// `_uscans._tcp` service type. `scope_id` is the zone of IPv6 link-local addresses, `0` if
+/// unknown.
+fn resolve_service(
+    cache: &RecordCache,
+    instance_name: &str,
+    tls: bool,
+    scope_id: u32,
+) -> Result<ScannerService, RejectionReason> {
+    // There must be one TXT record with metadata
+    let txt_record = cache
+        .named(instance_name)
+        .find_map(|kind| match kind {
+            RecordKind::TXT(txt) => Some(TxtRecord::parse(txt)),
+            _ => None,
+        })
+        .ok_or(RejectionReason::MissingTxtRecord)?;
+
+    // URL prefix and human readable name are required
+    let rs = txt_record
+        .rs
+        .clone()
+        .ok_or(RejectionReason::MissingResourcePath)?;
+    let ty = txt_record.ty.clone().ok_or(RejectionReason::MissingName)?;
+
+    // There must be one SRV record pointing to the address
+    let (srv_record, port) = cache
+        .named(instance_name)
+        .find_map(|kind| match kind {
+            RecordKind::SRV { target, port, .. } => Some((target, port)),
+            _ => None,
+        })
+        .ok_or(RejectionReason::MissingSrvRecord)?;
+
+    // There should be at least one A or AAAA record with IP address
+    let mut addresses = cache
+        .named(srv_record)
+        .filter_map(|kind| match kind {
+            RecordKind::A(ip_addr) => Some(SocketAddr::new(IpAddr::V4(*ip_addr), *port)),
+            RecordKind::AAAA(ip_addr) => {
+                let scope_id = if is_link_local(&SocketAddr::new(IpAddr::V6(*ip_addr), *port)) {
+                    scope_id
+                } else {
+                    0
+                };
+                Some(SocketAddr::V6(SocketAddrV6::new(
+                    *ip_addr, *port, 0, scope_id,
+                )))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if addresses.is_empty() {
+        return Err(RejectionReason::MissingAddress {
+            target: srv_record.to_owned(),
+        });
+    }
+    addresses.sort_by_key(|address| (address.is_ipv6(), is_link_local(address)));
+    addresses.dedup();
+
+    let scheme = if tls { "https" } else { "http" };
+    let base_urls = addresses
+        .iter()
+        .filter_map(|address| match address {
+            SocketAddr::V6(address) if is_link_local(&SocketAddr::V6(*address)) => {
+                // Unusable without zone
+                if address.scope_id() == 0 {
+                    return None;
+                }
+                Url::parse(&format!(
+                    "{}://{}:{}/{}",
+                    scheme,
+                    link_local_host(address),
+                    address.port(),
+                    rs
+                ))
+                .ok()
+            }
+            _ => Url::parse(&format!("{}://{}/{}", scheme, address, rs)).ok(),
+        })
+        .collect::<Vec<_>>();
+
+    if base_urls.is_empty() {
+        return Err(RejectionReason::OnlyLinkLocalAddresses {
+            target: srv_record.to_owned(),
+        });
+    }
+
+    Ok(ScannerService {
+        instance_name: instance_name.to_owned(),
+        tls,
+        addresses,
+        base_urls,
+        name: ty,
+        txt: txt_record,
+        interface: None,
+    })
+}
+
+/// Host name standing for the link-local `address` in URLs, which cannot carry its zone, e.g.
+/// `fe80--1s2.link-local.invalid` for `fe80::1%2`, after the `ipv6-literal.net` names of Windows.
+fn link_local_host(address: &SocketAddrV6) -> String {
+    format!(
+        "{}s{}.{}",
+        address.ip().to_string().replace(':', "-"),
+        address.scope_id(),
+        LINK_LOCAL_DOMAIN
+    )
+}
+
+/// Whether `address` is an IPv6 link-local (`fe80::/10`) address.
+fn is_link_local(address: &SocketAddr) -> bool {
+    match address {
+        SocketAddr::V4(_) => false,
+        SocketAddr::V6(address) => address.ip().segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    fn record(name: &str, ttl: u32, kind: RecordKind) -> Record {
+        Record {
+            name: name.to_owned(),
+            class: dns_parser::Class::IN,
+            ttl,
+            kind,
+        }
+    }
+
+    fn announcement(ttl: u32, ip_addr: Ipv4Addr) -> Response {
+        service_announcement("_uscan._tcp.local", 80, ttl, ip_addr)
+    }
+
+    fn service_announcement(
+        service_name: &str,
+        port: u16,
+        ttl: u32,
+        ip_addr: Ipv4Addr,
+    ) -> Response {
+        let instance_name = format!("Brother MFC-J497DW.{}", service_name);
+        let instance_name = instance_name.as_str();
+
+        Response {
+            answers: vec![record(
+                service_name,
+                ttl,
+                RecordKind::PTR(instance_name.to_owned()),
+            )],
+            nameservers: vec![],
+            additional: vec![
+                record(
+                    instance_name,
+                    ttl,
+                    RecordKind::TXT(vec![
+                        "txtvers=1".to_owned(),
+                        "ty=Brother MFC-J497DW".to_owned(),
+                        "rs=eSCL".to_owned(),
+                        "UUID=e3248000-80ce-11db-8000-3c2af4a1b2c3".to_owned(),
+                    ]),
+                ),
+                record(
+                    instance_name,
+                    ttl,
+                    RecordKind::SRV {
+                        priority: 0,
+                        weight: 0,
+                        port,
+                        target: "BRW0123456789AB.local".to_owned(),
+                    },
+                ),
+                record("BRW0123456789AB.local", ttl, RecordKind::A(ip_addr)),
+            ],
+        }
+    }
+
+    #[test]
+    pub fn test_txt_record_parse() {
+        let txt = TxtRecord::parse(&[
+            "txtvers=1",
+            "vers=2.63",
+            "ty=Brother MFC-J497DW",
+            "rs=/eSCL",
+            "UUID=e3248000-80ce-11db-8000-3c2af4a1b2c3",
+            "note=2nd floor",
+            "adminurl=http://10.0.0.2/net/net/airprint.html",
+            "representation=http://10.0.0.2/icons/device-icons-128.png",
+            "cs=color,grayscale,binary",
+            "pdl=application/pdf,image/jpeg",
+            "is=platen,adf",
+            "duplex=T",
+            "x-vendor=custom",
+        ]);
+
+        assert_eq!(txt.rs.as_deref(), Some("eSCL"));
+        assert_eq!(
+            txt.uuid.as_deref(),
+            Some("e3248000-80ce-11db-8000-3c2af4a1b2c3")
+        );
+        assert_eq!(txt.note.as_deref(), Some("2nd floor"));
+        assert_eq!(
+            txt.admin_url.as_ref().map(Url::as_str),
+            Some("http://10.0.0.2/net/net/airprint.html")
+        );
+        assert_eq!(txt.color_spaces, ["color", "grayscale", "binary"]);
+        assert_eq!(txt.document_formats, ["application/pdf", "image/jpeg"]);
+        assert_eq!(txt.input_sources, ["platen", "adf"]);
+        assert_eq!(txt.duplex, Some(true));
+        assert_eq!(txt.raw.get("x-vendor").map(String::as_str), Some("custom"));
+    }
+
+    #[test]
+    pub fn test_resolve_ipv6_addresses() {
+        let mut response = announcement(120, Ipv4Addr::new(10, 0, 0, 2));
+        for ip_addr in [
+            "fe80::3e2a:f4ff:fea1:b2c3".parse::<Ipv6Addr>().unwrap(),
+            "fd00::3e2a:f4ff:fea1:b2c3".parse::<Ipv6Addr>().unwrap(),
+        ] {
+            response.additional.push(record(
+                "BRW0123456789AB.local",
+                120,
+                RecordKind::AAAA(ip_addr),
+            ));
+        }
+
+        let mut browser = Browser::default();
+        let events = browser.handle_response(&response, Instant::now());
+        let service = match &events[..] {
+            [DiscoveryEvent::Added(service)] => service.clone(),
+            _ => panic!("unexpected events: {:?}", events),
+        };
+
+        assert_eq!(
+            service.addresses(),
+            [
+                "10.0.0.2:80".parse::<SocketAddr>().unwrap(),
+                "[fd00::3e2a:f4ff:fea1:b2c3]:80".parse().unwrap(),
+                "[fe80::3e2a:f4ff:fea1:b2c3]:80".parse().unwrap(),
+            ]
+        );
+        assert_eq!(
+            service.urls().iter().map(Url::as_str).collect::<Vec<_>>(),
+            [
+                "http://10.0.0.2/eSCL",
+                "http://[fd00::3e2a:f4ff:fea1:b2c3]/eSCL"
+            ]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    pub async fn test_discover_on_failed_interface() {
+        // No host has this address, so the multicast group cannot be joined on it
+        let unavailable = InterfaceSelector::Address(Ipv4Addr::new(198, 51, 100, 1));
+
+        assert!(matches!(
+            discover_stream_on(
+                &Interfaces::Selected(vec![unavailable.clone()]),
+                DEFAULT_QUERY_INTERVAL
+            ),
+            Err(DiscoverError::Io(_))
+        ));
+        assert!(discover_stream_on(
+            &Interfaces::Selected(vec![
+                unavailable,
+                InterfaceSelector::Address(Ipv4Addr::LOCALHOST)
+            ]),
+            DEFAULT_QUERY_INTERVAL
+        )
+        .is_ok());
+    }
+
+    #[test]
+    pub fn test_browse_on_interface() {
+        let interface = NetworkInterface {
+            name: "eth0".to_owned(),
+            index: 2,
+            address: Ipv4Addr::new(10, 0, 0, 100),
+        };
+
+        let mut response = announcement(120, Ipv4Addr::new(10, 0, 0, 2));
+        response.additional.push(record(
+            "BRW0123456789AB.local",
+            120,
+            RecordKind::AAAA("fe80::3e2a:f4ff:fea1:b2c3".parse().unwrap()),
+        ));
+
+        let mut browser = Browser::on_interface(Some(interface.clone()));
+        let events = browser.handle_response(&response, Instant::now());
+        let service = match &events[..] {
+            [DiscoveryEvent::Added(service)] => service.clone(),
+            _ => panic!("unexpected events: {:?}", events),
+        };
+
+        assert_eq!(service.interface(), Some(&interface));
+        assert_eq!(
+            service.addresses()[1],
+            "[fe80::3e2a:f4ff:fea1:b2c3%2]:80".parse().unwrap()
+        );
+        assert_eq!(
+            service.urls().iter().map(Url::as_str).collect::<Vec<_>>(),
+            [
+                "http://10.0.0.2/eSCL",
+                "http://fe80--3e2a-f4ff-fea1-b2c3s2.link-local.invalid/eSCL"
+            ]
+        );
+        assert_eq!(
+            service.link_local_hosts(),
+            [(
+                "fe80--3e2a-f4ff-fea1-b2c3s2.link-local.invalid".to_owned(),
+                service.addresses()[1]
+            )]
+        );
+
+        // Link-local addresses alone are usable with their zone only
+        response
+            .additional
+            .retain(|record| !matches!(record.kind, RecordKind::A(_)));
+        let mut browser = Browser::on_interface(Some(interface));
+        let events = browser.handle_response(&response, Instant::now());
+        assert!(matches!(&events[..], [DiscoveryEvent::Added(_)]));
+
+        let mut browser = Browser::default();
+        assert!(browser
+            .handle_response(&response, Instant::now())
+            .is_empty());
+        assert_eq!(
+            browser.diagnose()[0].result,
+            Err(RejectionReason::OnlyLinkLocalAddresses {
+                target: "BRW0123456789AB.local".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    pub fn test_merge_tls_service() {
+        let mut browser = Browser::default();
+        let now = Instant::now();
+
+        let ip_addr = Ipv4Addr::new(10, 0, 0, 2);
+        browser.handle_response(&announcement(120, ip_addr), now);
+        let events = browser.handle_response(
+            &service_announcement("_uscans._tcp.local", 443, 120, ip_addr),
+            now,
+        );
+        let service = match &events[..] {
+            [DiscoveryEvent::Updated(service)] => service.clone(),
+            _ => panic!("unexpected events: {:?}", events),
+        };
+
+        assert!(service.is_tls());
+        assert_eq!(
+            service.instance_name(),
+            "Brother MFC-J497DW._uscans._tcp.local"
+        );
+        assert_eq!(
+            service.urls().iter().map(Url::as_str).collect::<Vec<_>>(),
+            ["https://10.0.0.2/eSCL", "http://10.0.0.2/eSCL"]
+        );
+        assert_eq!(
+            service.addresses(),
+            [
+                "10.0.0.2:443".parse::<SocketAddr>().unwrap(),
+                "10.0.0.2:80".parse().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_diagnose_rejected_service() {
+        let mut response = announcement(120, Ipv4Addr::new(10, 0, 0, 2));
+        response
+            .additional
+            .retain(|record| !matches!(record.kind, RecordKind::A(_)));
+
+        let mut browser = Browser::default();
+        assert!(browser
+            .handle_response(&response, Instant::now())
+            .is_empty());
+
+        let diagnostics = browser.diagnose();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].instance_name,
+            "Brother MFC-J497DW._uscan._tcp.local"
+        );
+        assert_eq!(
+            diagnostics[0].result,
+            Err(RejectionReason::MissingAddress {
+                target: "BRW0123456789AB.local".to_owned()
+            })
+        );
+        assert_eq!(diagnostics[0].records.len(), 3);
+    }
+
+    #[test]
+    pub fn test_follow_ups() {
+        let mut browser = Browser::default();
+        let now = Instant::now();
+
+        // PTR record only
+        let mut response = announcement(120, Ipv4Addr::new(10, 0, 0, 2));
+        let additional = std::mem::take(&mut response.additional);
+        browser.handle_response(&response, now);
+        assert_eq!(
+            browser.follow_ups(now),
+            [FollowUp::Instance(
+                "Brother MFC-J497DW._uscan._tcp.local".to_owned()
+            )]
+        );
+        assert!(browser.follow_ups(now + Duration::from_secs(1)).is_empty());
+
+        // Answer with SRV and TXT records, but without address. TTLs are capped by the responder.
+        response.answers = additional
+            .into_iter()
+            .filter(|record| !matches!(record.kind, RecordKind::A(_)))
+            .map(|record| Record { ttl: 10, ..record })
+            .collect();
+        assert!(browser.handle_follow_up_response(&response, now).is_empty());
+        assert_eq!(
+            browser.follow_ups(now),
+            [FollowUp::Host("BRW0123456789AB.local".to_owned())]
+        );
+
+        response.answers = vec![record(
+            "BRW0123456789AB.local",
+            10,
+            RecordKind::A(Ipv4Addr::new(10, 0, 0, 2)),
+        )];
+        assert!(matches!(
+            &browser.handle_follow_up_response(&response, now)[..],
+            [DiscoveryEvent::Added(_)]
+        ));
+        assert!(browser.follow_ups(now).is_empty());
+        assert!(browser.expire(now + Duration::from_secs(60)).is_empty());
+    }
+
+    #[test]
+    pub fn test_browser_events() {
+        let mut browser = Browser::default();
+        let now = Instant::now();
+
+        let events = browser.handle_response(&announcement(120, Ipv4Addr::new(10, 0, 0, 2)), now);
+        let service = match &events[..] {
+            [DiscoveryEvent::Added(service)] => service.clone(),
+            _ => panic!("unexpected events: {:?}", events),
+        };
+        assert_eq!(service.url().as_str(), "http://10.0.0.2/eSCL");
+        assert_eq!(service.name(), "Brother MFC-J497DW");
+
+        // Same announcement again changes nothing
+        let events = browser.handle_response(&announcement(120, Ipv4Addr::new(10, 0, 0, 2)), now);
+        assert!(events.is_empty());
+
+        // Goodbye packet
+        let events = browser.handle_response(&announcement(0, Ipv4Addr::new(10, 0, 0, 2)), now);
+        assert_eq!(events, [DiscoveryEvent::Removed(service)]);
+
+        // Records expiring
+        browser.handle_response(&announcement(120, Ipv4Addr::new(10, 0, 0, 2)), now);
+        assert!(browser.expire(now + Duration::from_secs(60)).is_empty());
+        assert!(matches!(
+            &browser.expire(now + Duration::from_secs(121))[..],
+            [DiscoveryEvent::Removed(_)]
+        ));
+    }
+
+    /// Encodes a domain name without compression.
+    fn encode_name(name: &str) -> Vec<u8> {
+        let mut data = vec![];
+        for label in name.split('.') {
+            data.push(label.len() as u8);
+            data.extend_from_slice(label.as_bytes());
+        }
+        data.push(0);
+        data
+    }
+
+    /// Runs a minimal DNS server answering queries from `zone`, where each entry is the record
+    /// name, type and RDATA. TXT answers are truncated over UDP, and only served in full over TCP
+    /// if `tcp` is set.
+    async fn dns_stand_in(
+        zone: Vec<(&'static str, QueryType, Vec<u8>)>,
+        ttl: u32,
+        tcp: bool,
+    ) -> SocketAddr {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server = socket.local_addr().unwrap();
+        let zone = std::sync::Arc::new(zone);
+
+        let udp_zone = zone.clone();
+        tokio::spawn(async move {
+            let mut buffer = vec![0; 512];
+            loop {
+                let (len, client) = socket.recv_from(&mut buffer).await.unwrap();
+                let response = dns_stand_in_response(&udp_zone, ttl, &buffer[..len], true);
+                socket.send_to(&response, client).await.unwrap();
+            }
+        });
+
+        if !tcp {
+            return server;
+        }
+
+        let listener = tokio::net::TcpListener::bind(server).await.unwrap();
+        tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut len = [0; 2];
+                stream.read_exact(&mut len).await.unwrap();
+                let mut query = vec![0; u16::from_be_bytes(len).into()];
+                stream.read_exact(&mut query).await.unwrap();
+
+                let response = dns_stand_in_response(&zone, ttl, &query, false);
+                let mut message = (response.len() as u16).to_be_bytes().to_vec();
+                message.extend(response);
+                stream.write_all(&message).await.unwrap();
+            }
+        });
+
+        server
+    }
+
+    fn dns_stand_in_response(
+        zone: &[(&'static str, QueryType, Vec<u8>)],
+        ttl: u32,
+        query: &[u8],
+        udp: bool,
+    ) -> Vec<u8> {
+        let packet = dns_parser::Packet::parse(query).unwrap();
+        let question = &packet.questions[0];
+        let qname = question.qname.to_string();
+        let truncated = udp && question.qtype == QueryType::TXT;
+
+        let answers = zone
+            .iter()
+            .filter(|(name, query_type, _)| {
+                !truncated && name.eq_ignore_ascii_case(&qname) && *query_type == question.qtype
+            })
+            .collect::<Vec<_>>();
+
+        // Header with the query ID, then the question copied from the query
+        let flags = if truncated { 0x83 } else { 0x81 };
+        let mut response = vec![query[0], query[1], flags, 0x80, 0, 1];
+        response.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+        response.extend_from_slice(&[0, 0, 0, 0]);
+        response.extend_from_slice(&query[12..]);
+
+        for (name, query_type, rdata) in answers {
+            response.extend(encode_name(name));
+            response.extend_from_slice(&(*query_type as u16).to_be_bytes());
+            response.extend_from_slice(&[0, 1]);
+            response.extend_from_slice(&ttl.to_be_bytes());
+            response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            response.extend_from_slice(rdata);
+        }
+
+        response
+    }
+
+    fn wide_area_zone() -> Vec<(&'static str, QueryType, Vec<u8>)> {
+        let mut srv = vec![0, 0, 0, 0, 0x1f, 0x90];
+        srv.extend(encode_name("scanner.branch.example.com"));
+
+        let mut txt = vec![];
+        for entry in ["txtvers=1", "ty=Canon TS7450", "rs=eSCL"] {
+            txt.push(entry.len() as u8);
+            txt.extend_from_slice(entry.as_bytes());
+        }
+
+        vec![
+            (
+                "_uscan._tcp.branch.example.com",
+                QueryType::PTR,
+                encode_name("Office._uscan._tcp.branch.example.com"),
+            ),
+            ("Office._uscan._tcp.branch.example.com", QueryType::SRV, srv),
+            ("Office._uscan._tcp.branch.example.com", QueryType::TXT, txt),
+            (
+                "scanner.branch.example.com",
+                QueryType::A,
+                vec![10, 1, 2, 3],
+            ),
+        ]
+    }
+
+    #[tokio::test]
+    pub async fn test_discover_wide_area() {
+        // Records with a TTL of zero are used, just not cached
+        for ttl in [3600, 0] {
+            let server = dns_stand_in(wide_area_zone(), ttl, true).await;
+
+            let services =
+                discover_wide_area("branch.example.com.", server, Duration::from_millis(500))
+                    .await
+                    .unwrap();
+
+            assert_eq!(services.len(), 1);
+            assert_eq!(services[0].url().as_str(), "http://10.1.2.3:8080/eSCL");
+            assert_eq!(services[0].name(), "Canon TS7450");
+            assert_eq!(
+                services[0].instance_name(),
+                "Office._uscan._tcp.branch.example.com"
+            );
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_discover_wide_area_failed_query() {
+        // TXT queries fail, with nothing accepting TCP connections
+        let server = dns_stand_in(wide_area_zone(), 3600, false).await;
+
+        let services = discover_wide_area("branch.example.com", server, Duration::from_millis(500))
+            .await
+            .unwrap();
+        assert!(services.is_empty());
+    }
+}
@@ -0,0 +1,454 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use dns_parser::{Builder, Class, Packet, QueryClass, QueryType, RData, ResourceRecord};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+};
+
+use crate::interface::NetworkInterface;
+
+/// Largest DNS message over UDP handled. mDNS allows messages up to 9000 bytes.
+const MAX_MESSAGE_SIZE: usize = 9000;
+
+/// mDNS multicast group.
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+
+/// mDNS port, shared with any responder running on the host.
+const MDNS_PORT: u16 = 5353;
+
+/// A DNS response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Response {
+    pub answers: Vec<Record>,
+    pub nameservers: Vec<Record>,
+    pub additional: Vec<Record>,
+}
+
+/// Any type of DNS record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    pub class: Class,
+    pub ttl: u32,
+    pub kind: RecordKind,
+}
+
+/// A specific DNS record variant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordKind {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    CNAME(String),
+    MX {
+        preference: u16,
+        exchange: String,
+    },
+    NS(String),
+    SRV {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    TXT(Vec<String>),
+    PTR(String),
+    /// A record type not handled, with its raw data if available.
+    Unimplemented(Vec<u8>),
+}
+
+/// A socket on the mDNS port, sending queries and receiving the responses of a single interface.
+#[derive(Debug)]
+pub(crate) struct MdnsSocket {
+    socket: UdpSocket,
+    /// Index of the interface responses are accepted from, `0` for any
+    interface_index: u32,
+}
+
+impl MdnsSocket {
+    /// Binds the mDNS port and joins the multicast group on `interface`, which queries are also
+    /// sent on. `None` leaves the choice of the interface to the operating system.
+    ///
+    /// Sockets bound to the mDNS port receive multicast packets from every interface by default,
+    /// whichever interface they joined the group on. On Linux, this is turned off, and packets are
+    /// filtered by the interface they arrived on, so that responses are attributed to the right
+    /// interface when browsing several of them.
+    pub fn bind(interface: Option<&NetworkInterface>) -> std::io::Result<Self> {
+        let interface_addr = interface.map_or(Ipv4Addr::UNSPECIFIED, |interface| interface.address);
+
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+        socket.set_reuse_port(true)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MDNS_PORT)).into())?;
+
+        socket.join_multicast_v4(&MDNS_GROUP, &interface_addr)?;
+        if interface.is_some() {
+            socket.set_multicast_if_v4(&interface_addr)?;
+        }
+        socket.set_multicast_loop_v4(false)?;
+        #[cfg(target_os = "linux")]
+        set_socket_option(&socket, libc::IP_MULTICAST_ALL, 0)?;
+
+        Self::from_std(
+            socket.into(),
+            interface.map_or(0, |interface| interface.index),
+        )
+    }
+
+    fn from_std(socket: std::net::UdpSocket, interface_index: u32) -> std::io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        #[cfg(target_os = "linux")]
+        set_socket_option(&socket, libc::IP_PKTINFO, 1)?;
+
+        Ok(Self {
+            socket: UdpSocket::from_std(socket)?,
+            interface_index,
+        })
+    }
+
+    /// Sends `packet` to the mDNS multicast group.
+    pub async fn send(&self, packet: &[u8]) -> std::io::Result<()> {
+        self.socket
+            .send_to(packet, SocketAddr::from((MDNS_GROUP, MDNS_PORT)))
+            .await?;
+        Ok(())
+    }
+
+    /// Receives the next response, skipping queries, malformed packets, and packets received on
+    /// other interfaces.
+    pub async fn recv(&self) -> std::io::Result<Response> {
+        let mut buffer = vec![0; MAX_MESSAGE_SIZE];
+
+        loop {
+            let (len, interface_index) = self.recv_from_interface(&mut buffer).await?;
+            if self.interface_index != 0
+                && interface_index.is_some_and(|index| index != self.interface_index)
+            {
+                continue;
+            }
+
+            if let Ok(packet) = Packet::parse(&buffer[..len]) {
+                if !packet.header.query {
+                    return Ok(Response::from_packet(&packet));
+                }
+            }
+        }
+    }
+
+    /// Receives a packet along with the index of the interface it arrived on, if known.
+    #[cfg(target_os = "linux")]
+    async fn recv_from_interface(
+        &self,
+        buffer: &mut [u8],
+    ) -> std::io::Result<(usize, Option<u32>)> {
+        use std::os::fd::AsRawFd;
+
+        let fd = self.socket.as_raw_fd();
+        self.socket
+            .async_io(tokio::io::Interest::READABLE, || recv_pktinfo(fd, buffer))
+            .await
+    }
+
+    /// Sockets are only bound to the default interface here, see `Interfaces::resolve`.
+    #[cfg(not(target_os = "linux"))]
+    async fn recv_from_interface(
+        &self,
+        buffer: &mut [u8],
+    ) -> std::io::Result<(usize, Option<u32>)> {
+        let (len, _) = self.socket.recv_from(buffer).await?;
+        Ok((len, None))
+    }
+}
+
+impl Response {
+    pub fn from_packet(packet: &Packet) -> Self {
+        Self {
+            answers: packet.answers.iter().map(Record::from).collect(),
+            nameservers: packet.nameservers.iter().map(Record::from).collect(),
+            additional: packet.additional.iter().map(Record::from).collect(),
+        }
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.answers
+            .iter()
+            .chain(self.nameservers.iter())
+            .chain(self.additional.iter())
+    }
+
+    pub fn records_mut(&mut self) -> impl Iterator<Item = &mut Record> {
+        self.answers
+            .iter_mut()
+            .chain(self.nameservers.iter_mut())
+            .chain(self.additional.iter_mut())
+    }
+}
+
+impl From<&ResourceRecord<'_>> for Record {
+    fn from(value: &ResourceRecord<'_>) -> Self {
+        Self {
+            name: value.name.to_string(),
+            class: value.cls,
+            ttl: value.ttl,
+            kind: (&value.data).into(),
+        }
+    }
+}
+
+impl From<&RData<'_>> for RecordKind {
+    fn from(value: &RData<'_>) -> Self {
+        match value {
+            RData::A(record) => Self::A(record.0),
+            RData::AAAA(record) => Self::AAAA(record.0),
+            RData::CNAME(name) => Self::CNAME(name.to_string()),
+            RData::MX(record) => Self::MX {
+                preference: record.preference,
+                exchange: record.exchange.to_string(),
+            },
+            RData::NS(name) => Self::NS(name.to_string()),
+            RData::SRV(record) => Self::SRV {
+                priority: record.priority,
+                weight: record.weight,
+                port: record.port,
+                target: record.target.to_string(),
+            },
+            RData::TXT(txt) => Self::TXT(
+                txt.iter()
+                    .map(|entry| String::from_utf8_lossy(entry).into_owned())
+                    .collect(),
+            ),
+            RData::PTR(name) => Self::PTR(name.to_string()),
+            // The parser doesn't keep the raw data of SOA records
+            RData::SOA(_) => Self::Unimplemented(vec![]),
+            RData::Unknown(data) => Self::Unimplemented(data.to_vec()),
+        }
+    }
+}
+
+/// Builds a DNS query with `questions`.
+pub(crate) fn build_query(
+    id: u16,
+    questions: &[(&str, QueryType)],
+    recursion_desired: bool,
+) -> std::io::Result<Vec<u8>> {
+    let mut builder = Builder::new_query(id, recursion_desired);
+    for (name, query_type) in questions {
+        builder.add_question(name, false, *query_type, QueryClass::IN);
+    }
+
+    builder
+        .build()
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "DNS query too large"))
+}
+
+/// Sends a single DNS query with `questions` to `server` from an ephemeral port, and waits up to
+/// `timeout` for the matching response.
+///
+/// When sent to the mDNS multicast group, this is a "legacy unicast" query (RFC 6762 section 6.7)
+/// which responders answer directly to the sender, and `multicast_interface` selects the interface
+/// it's sent on. Otherwise, truncated responses are followed by the same query over TCP (RFC 7766).
+/// Returns `None` if no response arrives in time.
+pub(crate) async fn query(
+    server: SocketAddr,
+    questions: &[(&str, QueryType)],
+    recursion_desired: bool,
+    multicast_interface: Option<Ipv4Addr>,
+    timeout: Duration,
+) -> std::io::Result<Option<Response>> {
+    // Unpredictable, so that off-path attackers can't forge answers
+    let id = rand::random::<u16>();
+
+    let packet_data = build_query(id, questions, recursion_desired)?;
+
+    let bind_addr: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    if let Some(interface) = multicast_interface {
+        SockRef::from(&socket).set_multicast_if_v4(&interface)?;
+    }
+    socket.send_to(&packet_data, server).await?;
+
+    // Yields `None` for truncated responses that can be queried again over TCP. mDNS has no TCP
+    // fallback, so truncated mDNS responses are used as is.
+    let receive = async {
+        let mut buffer = vec![0; MAX_MESSAGE_SIZE];
+        loop {
+            let (len, source) = socket.recv_from(&mut buffer).await?;
+            // Multicast queries are answered from each responder's own address
+            if !server.ip().is_multicast() && source != server {
+                continue;
+            }
+
+            // Unrelated or malformed packets are skipped
+            if let Ok(packet) = Packet::parse(&buffer[..len]) {
+                if !packet.header.query && packet.header.id == id {
+                    if packet.header.truncated && !server.ip().is_multicast() {
+                        return Ok(None);
+                    }
+                    return Ok(Some(Response::from_packet(&packet)));
+                }
+            }
+        }
+    };
+
+    match tokio::time::timeout(timeout, receive).await {
+        Ok(Ok(Some(response))) => Ok(Some(response)),
+        Ok(Ok(None)) => query_tcp(server, &packet_data, id, timeout).await,
+        Ok(Err(err)) => Err(err),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Sends the query in `packet_data` to `server` over TCP, and waits up to `timeout` for the
+/// matching response.
+async fn query_tcp(
+    server: SocketAddr,
+    packet_data: &[u8],
+    id: u16,
+    timeout: Duration,
+) -> std::io::Result<Option<Response>> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(server).await?;
+
+        // Messages are prefixed with their length
+        let mut message = (packet_data.len() as u16).to_be_bytes().to_vec();
+        message.extend_from_slice(packet_data);
+        stream.write_all(&message).await?;
+
+        loop {
+            let mut len = [0; 2];
+            stream.read_exact(&mut len).await?;
+            let mut buffer = vec![0; u16::from_be_bytes(len).into()];
+            stream.read_exact(&mut buffer).await?;
+
+            if let Ok(packet) = Packet::parse(&buffer) {
+                if !packet.header.query && packet.header.id == id {
+                    return Ok(Response::from_packet(&packet));
+                }
+            }
+        }
+    };
+
+    match tokio::time::timeout(timeout, exchange).await {
+        Ok(result) => result.map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_socket_option<S>(socket: &S, option: libc::c_int, value: libc::c_int) -> std::io::Result<()>
+where
+    S: std::os::fd::AsRawFd,
+{
+    // SAFETY: `value` outlives the call, and its size is passed along.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            option,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Receives a packet on a socket with `IP_PKTINFO` enabled, along with the index of the interface
+/// it arrived on.
+#[cfg(target_os = "linux")]
+fn recv_pktinfo(fd: libc::c_int, buffer: &mut [u8]) -> std::io::Result<(usize, Option<u32>)> {
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr().cast(),
+        iov_len: buffer.len(),
+    };
+    // Room for a single `in_pktinfo`, aligned for `cmsghdr`
+    let mut control = [0u64; 8];
+
+    // SAFETY: `msghdr` is plain data, for which all zeroes is valid.
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    // SAFETY: `msg` points to `buffer` and `control`, which outlive the call.
+    let len = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+    if len < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let mut interface_index = None;
+    // SAFETY: control messages are walked with the `CMSG_*` macros within `msg_controllen`, as
+    // set by `recvmsg`. `in_pktinfo` is read unaligned.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::IPPROTO_IP && (*cmsg).cmsg_type == libc::IP_PKTINFO {
+                let info =
+                    std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in_pktinfo);
+                interface_index = Some(info.ipi_ifindex as u32);
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    Ok((len as usize, interface_index))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_mdns_socket_interface() {
+        // SAFETY: the name is a C string literal.
+        let loopback_index = unsafe { libc::if_nametoindex(c"lo".as_ptr()) };
+        assert_ne!(loopback_index, 0);
+
+        // A response, i.e. a query with the QR bit set
+        let mut packet = build_query(0, &[("_uscan._tcp.local", QueryType::PTR)], false).unwrap();
+        packet[2] |= 0x80;
+
+        let bind = |interface_index: u32| {
+            let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            let address = socket.local_addr().unwrap();
+            (
+                MdnsSocket::from_std(socket, interface_index).unwrap(),
+                address,
+            )
+        };
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        // Received on the loopback interface, and attributed to it
+        let (socket, address) = bind(loopback_index);
+        sender.send_to(&packet[..], address).await.unwrap();
+        let mut buffer = vec![0; MAX_MESSAGE_SIZE];
+        let (len, interface_index) = socket.recv_from_interface(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..len], &packet[..]);
+        assert_eq!(interface_index, Some(loopback_index));
+
+        sender.send_to(&packet[..], address).await.unwrap();
+        socket.recv().await.unwrap();
+
+        // Dropped by a socket for another interface
+        let (socket, address) = bind(loopback_index + 1);
+        sender.send_to(&packet[..], address).await.unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), socket.recv())
+                .await
+                .is_err()
+        );
+    }
+}
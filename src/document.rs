@@ -0,0 +1,387 @@
+use std::{
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures_util::{
+    io::AsyncRead,
+    stream::{Stream, StreamExt, TryStreamExt},
+};
+use reqwest::header::{HeaderMap, CONTENT_LOCATION, CONTENT_TYPE};
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use crate::Error;
+
+/// A scanned document fully downloaded into memory, as returned by
+/// [ScanJob::next_document](crate::ScanJob::next_document).
+#[derive(Debug, Clone)]
+pub struct Document {
+    content_type: Option<String>,
+    content_location: Option<Url>,
+    data: Bytes,
+}
+
+/// A scanned document whose body is being streamed from the scanner in chunks, as returned by
+/// [ScanJob::next_document_stream](crate::ScanJob::next_document_stream).
+///
+/// Unlike [ScanJob::next_document](crate::ScanJob::next_document), the document is never fully
+/// held in memory, which makes it suitable for large, high resolution scans.
+pub struct DocumentStream {
+    content_type: Option<String>,
+    content_location: Option<Url>,
+    content_length: Option<u64>,
+    first_chunk: Option<Bytes>,
+    inner: Pin<Box<dyn Stream<Item = Result<Bytes, Error>> + Send>>,
+}
+
+impl Document {
+    pub(crate) async fn from_response(response: reqwest::Response) -> Result<Self, Error> {
+        let header_content_type = content_type_from_headers(response.headers());
+        let octet_stream = is_octet_stream(response.headers());
+        let content_location = content_location_from_response(&response);
+
+        let data = response.bytes().await.map_err(Error::Http)?;
+
+        Ok(Self {
+            content_type: resolve_content_type(header_content_type, octet_stream, &data),
+            content_location,
+            data,
+        })
+    }
+
+    /// MIME type of the document (e.g. `image/jpeg`). This is taken from the `Content-Type`
+    /// header when present, and otherwise guessed from the leading bytes of the document. The
+    /// generic `application/octet-stream` type is only reported if guessing fails.
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Conventional file extension (without the leading dot) for the document's MIME type.
+    pub fn file_extension(&self) -> Option<&'static str> {
+        file_extension(self.content_type.as_deref()?)
+    }
+
+    /// URL of the page, if the scanner returned one via the `Content-Location` header.
+    pub fn content_location(&self) -> Option<&Url> {
+        self.content_location.as_ref()
+    }
+
+    /// Size of the document in bytes.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Raw document content.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.data
+    }
+}
+
+impl DocumentStream {
+    pub(crate) async fn from_response(response: reqwest::Response) -> Result<Self, Error> {
+        let header_content_type = content_type_from_headers(response.headers());
+        let octet_stream = is_octet_stream(response.headers());
+        let content_location = content_location_from_response(&response);
+        let content_length = response.content_length();
+
+        let mut inner = Box::pin(response.bytes_stream().map_err(Error::Http));
+
+        // The first chunk is read eagerly so that the content type can be sniffed when the
+        // scanner doesn't report it. It's handed out again as the first item of the stream.
+        let first_chunk = inner.next().await.transpose()?;
+
+        let content_type = resolve_content_type(
+            header_content_type,
+            octet_stream,
+            first_chunk.as_deref().unwrap_or_default(),
+        );
+
+        Ok(Self {
+            content_type,
+            content_location,
+            content_length,
+            first_chunk,
+            inner,
+        })
+    }
+
+    /// MIME type of the document (e.g. `image/jpeg`). This is taken from the `Content-Type`
+    /// header when present, and otherwise guessed from the leading bytes of the document. The
+    /// generic `application/octet-stream` type is only reported if guessing fails.
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Conventional file extension (without the leading dot) for the document's MIME type.
+    pub fn file_extension(&self) -> Option<&'static str> {
+        file_extension(self.content_type.as_deref()?)
+    }
+
+    /// URL of the page, if the scanner returned one via the `Content-Location` header.
+    pub fn content_location(&self) -> Option<&Url> {
+        self.content_location.as_ref()
+    }
+
+    /// Size of the document in bytes, if announced by the scanner via `Content-Length`. Scanners
+    /// often use chunked transfer encoding instead, in which case this is `None`.
+    pub fn content_length(&self) -> Option<u64> {
+        self.content_length
+    }
+
+    /// Converts the stream into an [AsyncRead] implementation.
+    pub fn into_async_read(self) -> impl AsyncRead + Send + Unpin {
+        self.map_err(std::io::Error::other).into_async_read()
+    }
+
+    /// Writes the document into a file at `path`, creating the file if it doesn't exist and
+    /// truncating it if it does. Returns the number of bytes written.
+    pub async fn write_to_file<P>(mut self, path: P) -> Result<u64, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = tokio::fs::File::create(path).await.map_err(Error::Io)?;
+
+        let mut bytes_written = 0;
+        while let Some(chunk) = self.next().await {
+            let chunk = chunk?;
+            file.write_all(&chunk).await.map_err(Error::Io)?;
+            bytes_written += chunk.len() as u64;
+        }
+
+        file.flush().await.map_err(Error::Io)?;
+
+        Ok(bytes_written)
+    }
+}
+
+impl Stream for DocumentStream {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(first_chunk) = self.first_chunk.take() {
+            return Poll::Ready(Some(Ok(first_chunk)));
+        }
+
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+impl std::fmt::Debug for DocumentStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DocumentStream")
+            .field("content_type", &self.content_type)
+            .field("content_location", &self.content_location)
+            .field("content_length", &self.content_length)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Extracts the MIME type from the `Content-Type` header, dropping any parameters. The generic
+/// `application/octet-stream` type is treated as absent so that sniffing can take over.
+fn content_type_from_headers(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    if mime.is_empty() || mime == "application/octet-stream" {
+        None
+    } else {
+        Some(mime)
+    }
+}
+
+/// Whether the `Content-Type` header is the generic `application/octet-stream` type.
+fn is_octet_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/octet-stream"))
+}
+
+/// Picks the MIME type of a document: the one from the headers if specific, then the one sniffed
+/// from the leading bytes `data`, and finally `application/octet-stream` if that's what the
+/// scanner declared.
+fn resolve_content_type(
+    header_content_type: Option<String>,
+    octet_stream: bool,
+    data: &[u8],
+) -> Option<String> {
+    header_content_type
+        .or_else(|| sniff_content_type(data))
+        .or_else(|| octet_stream.then(|| "application/octet-stream".to_owned()))
+}
+
+/// Resolves the `Content-Location` header, which is allowed to be relative, against the request
+/// URL. Like job URLs, the scheme, host and port of the request are kept.
+fn content_location_from_response(response: &reqwest::Response) -> Option<Url> {
+    let content_location = response.headers().get(CONTENT_LOCATION)?.to_str().ok()?;
+    crate::resolve_url(response.url(), content_location)
+}
+
+/// Guesses the MIME type from the magic bytes of formats scanners commonly produce.
+fn sniff_content_type(data: &[u8]) -> Option<String> {
+    let mime = if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        "image/jpeg"
+    } else if data.starts_with(b"%PDF-") {
+        "application/pdf"
+    } else if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+        "image/tiff"
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else {
+        return None;
+    };
+
+    Some(mime.to_owned())
+}
+
+fn file_extension(content_type: &str) -> Option<&'static str> {
+    Some(match content_type {
+        "image/jpeg" => "jpg",
+        "application/pdf" => "pdf",
+        "image/tiff" => "tiff",
+        "image/png" => "png",
+        "image/heic" | "image/heif" => "heic",
+        "image/bmp" => "bmp",
+        "application/octet-stream" => "bin",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::io::AsyncReadExt;
+
+    use super::*;
+    use crate::test_server::{self, Response};
+
+    /// JPEG data sent in several chunks by the test server.
+    fn chunks() -> Vec<Vec<u8>> {
+        vec![
+            b"\xff\xd8\xff\xe0\x00\x10JFIF".to_vec(),
+            vec![0x42; 5000],
+            b"\xff\xd9".to_vec(),
+        ]
+    }
+
+    async fn document_stream() -> DocumentStream {
+        let address = test_server::serve(|_| Response::new("200 OK").chunked(chunks())).await;
+        let response = reqwest::get(format!("http://{}/eSCL/ScanJobs/1/NextDocument", address))
+            .await
+            .unwrap();
+
+        DocumentStream::from_response(response).await.unwrap()
+    }
+
+    #[tokio::test]
+    pub async fn test_document_stream() {
+        let expected = chunks().concat();
+
+        // The first chunk, read for sniffing, is handed out again
+        let stream = document_stream().await;
+        assert_eq!(stream.content_type(), Some("image/jpeg"));
+        assert_eq!(stream.content_length(), None);
+        let received = stream.try_collect::<Vec<_>>().await.unwrap();
+        assert!(received.len() > 1);
+        assert_eq!(received.concat(), expected);
+
+        let mut data = vec![];
+        document_stream()
+            .await
+            .into_async_read()
+            .read_to_end(&mut data)
+            .await
+            .unwrap();
+        assert_eq!(data, expected);
+
+        let path = std::env::temp_dir().join(format!("escl-test-{}.jpg", std::process::id()));
+        let bytes_written = document_stream().await.write_to_file(&path).await.unwrap();
+        let written = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bytes_written, expected.len() as u64);
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    pub fn test_sniff_content_type() {
+        for (data, expected) in [
+            (&b"\xff\xd8\xff\xe0\x00\x10JFIF"[..], Some("image/jpeg")),
+            (&b"%PDF-1.4\n"[..], Some("application/pdf")),
+            (&b"II*\0\x08\0\0\0"[..], Some("image/tiff")),
+            (&b"MM\0*\0\0\0\x08"[..], Some("image/tiff")),
+            (&b"\x89PNG\r\n\x1a\n"[..], Some("image/png")),
+            (&b"plain text"[..], None),
+            (&b""[..], None),
+        ] {
+            assert_eq!(sniff_content_type(data).as_deref(), expected);
+        }
+    }
+
+    #[test]
+    pub fn test_content_type_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "Image/JPEG; charset=binary".parse().unwrap());
+        assert_eq!(
+            content_type_from_headers(&headers).as_deref(),
+            Some("image/jpeg")
+        );
+
+        headers.insert(CONTENT_TYPE, "application/octet-stream".parse().unwrap());
+        assert_eq!(content_type_from_headers(&headers), None);
+    }
+
+    #[test]
+    pub fn test_octet_stream_fallback() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "application/octet-stream".parse().unwrap());
+        let octet_stream = is_octet_stream(&headers);
+        assert!(octet_stream);
+
+        // Sniffing takes precedence over the generic type
+        assert_eq!(
+            resolve_content_type(None, octet_stream, b"%PDF-1.4\n").as_deref(),
+            Some("application/pdf")
+        );
+
+        let content_type = resolve_content_type(None, octet_stream, b"raw data");
+        assert_eq!(content_type.as_deref(), Some("application/octet-stream"));
+        assert_eq!(file_extension(&content_type.unwrap()), Some("bin"));
+
+        assert_eq!(resolve_content_type(None, false, b"raw data"), None);
+    }
+
+    #[tokio::test]
+    pub async fn test_content_location() {
+        let address = test_server::serve(|_| {
+            Response::new("200 OK")
+                .header("Content-Location", "http://localhost:8080/eSCL/Pages/1")
+                .body("%PDF-1.4\n")
+        })
+        .await;
+        let response = reqwest::get(format!("http://{}/eSCL/ScanJobs/1/NextDocument", address))
+            .await
+            .unwrap();
+
+        let document = Document::from_response(response).await.unwrap();
+        assert_eq!(
+            document.content_location().map(Url::as_str),
+            Some(format!("http://{}/eSCL/Pages/1", address).as_str())
+        );
+    }
+}
@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+use crate::capabilities::ColorMode;
+
+/// Information about the image most recently produced by a scan job, as reported by the
+/// `ScanImageInfo` endpoint.
+///
+/// Dimensions reported here reflect what the scanner actually produced, which can differ from
+/// what was requested in [ScanSettings](crate::settings::ScanSettings) (e.g. when auto-cropping
+/// or when the requested region gets adjusted to hardware constraints).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ScanImageInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_uri: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_uuid: Option<String>,
+    /// Width of the image in pixels
+    pub actual_width: u32,
+    /// Height of the image in pixels
+    pub actual_height: u32,
+    /// Number of bytes per line of raw image data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actual_bytes_per_line: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x_resolution: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y_resolution: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_mode: Option<ColorMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blank_page_detected: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_image_info_deser() {
+        let image_info = serde_xml_rs::from_str::<ScanImageInfo>(include_str!(
+            "../test-data/image_info/rgb24_letter.xml"
+        ))
+        .expect("image info deserializing failure");
+
+        assert_eq!(image_info.actual_width, 2550);
+        assert_eq!(image_info.actual_height, 3300);
+        assert_eq!(image_info.actual_bytes_per_line, Some(7650));
+        assert_eq!(image_info.x_resolution, Some(300));
+        assert_eq!(image_info.color_mode, Some(ColorMode::RGB24));
+    }
+}
@@ -0,0 +1,113 @@
+use std::{fmt::Display, net::Ipv4Addr};
+
+/// A local IPv4 network interface that discovery can run on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NetworkInterface {
+    pub(crate) name: String,
+    pub(crate) index: u32,
+    pub(crate) address: Ipv4Addr,
+}
+
+impl NetworkInterface {
+    /// Creates an interface identified by its IPv4 `address` only, for platforms where interfaces
+    /// can't be listed.
+    pub fn from_address(address: Ipv4Addr) -> Self {
+        Self {
+            name: address.to_string(),
+            index: 0,
+            address,
+        }
+    }
+
+    /// Interface name (e.g. `eth0`)
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Interface index, used as zone of IPv6 link-local addresses. `0` if unknown.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// IPv4 address of the host on the interface, used as source of multicast queries
+    pub fn address(&self) -> Ipv4Addr {
+        self.address
+    }
+}
+
+impl Display for NetworkInterface {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.name, self.address)
+    }
+}
+
+/// Lists the IPv4 interfaces that are up and support multicast, excluding loopback. Interfaces
+/// with several IPv4 addresses are listed once per address.
+#[cfg(unix)]
+pub fn network_interfaces() -> std::io::Result<Vec<NetworkInterface>> {
+    use std::ffi::CStr;
+
+    let mut ifaddrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: `ifaddrs` is a valid out pointer. The list is freed below.
+    if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let mut interfaces = vec![];
+
+    let mut cursor = ifaddrs;
+    while !cursor.is_null() {
+        // SAFETY: `cursor` points to an entry of the list returned by `getifaddrs`.
+        let ifaddr = unsafe { &*cursor };
+        cursor = ifaddr.ifa_next;
+
+        let flags = ifaddr.ifa_flags as libc::c_int;
+        if ifaddr.ifa_addr.is_null()
+            || flags & libc::IFF_UP == 0
+            || flags & libc::IFF_MULTICAST == 0
+            || flags & libc::IFF_LOOPBACK != 0
+        {
+            continue;
+        }
+
+        // SAFETY: `ifa_addr` is non-null, and points to a `sockaddr_in` for `AF_INET`.
+        let address = unsafe {
+            if (*ifaddr.ifa_addr).sa_family as libc::c_int != libc::AF_INET {
+                continue;
+            }
+            let sockaddr = &*(ifaddr.ifa_addr as *const libc::sockaddr_in);
+            Ipv4Addr::from(u32::from_be(sockaddr.sin_addr.s_addr))
+        };
+
+        // SAFETY: `ifa_name` is a valid NUL-terminated string.
+        let (name, index) = unsafe {
+            (
+                CStr::from_ptr(ifaddr.ifa_name)
+                    .to_string_lossy()
+                    .into_owned(),
+                libc::if_nametoindex(ifaddr.ifa_name),
+            )
+        };
+
+        interfaces.push(NetworkInterface {
+            name,
+            index,
+            address,
+        });
+    }
+
+    // SAFETY: `ifaddrs` was returned by `getifaddrs` and is not used anymore.
+    unsafe { libc::freeifaddrs(ifaddrs) };
+
+    Ok(interfaces)
+}
+
+/// Lists the IPv4 interfaces that are up and support multicast, excluding loopback. Not
+/// supported on this platform: use [NetworkInterface::from_address] instead.
+#[cfg(not(unix))]
+pub fn network_interfaces() -> std::io::Result<Vec<NetworkInterface>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "listing network interfaces is not supported on this platform",
+    ))
+}
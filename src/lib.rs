@@ -1,11 +1,24 @@
-use std::{fmt::Display, time::Duration};
+use std::{
+    fmt::Display,
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use futures_util::{pin_mut, stream::StreamExt};
-use mdns::RecordKind;
-use reqwest::{Client, StatusCode};
+pub use ipnet::IpNet;
+use reqwest::{
+    header::{CONTENT_TYPE, RETRY_AFTER},
+    Client, Method, StatusCode,
+};
 use serde::de::DeserializeOwned;
 pub use url::Url;
 
+pub use reqwest;
+
 pub mod capabilities;
 use capabilities::ScannerCapabilities;
 
@@ -13,39 +26,148 @@ pub mod status;
 use status::ScannerStatus;
 
 pub mod settings;
-use settings::ScanSettings;
+use settings::{DuplexPageOrder, ScanSettings};
+
+pub mod image_info;
+use image_info::ScanImageInfo;
+
+pub mod document;
+use document::{Document, DocumentStream};
+
+pub mod retry;
+use retry::RetryPolicy;
+
+pub mod auth;
+use auth::{Authenticator, Credentials};
+
+pub mod pinning;
+use pinning::{
+    CertificateFingerprint, CertificatePinning, CertificateStore, MemoryCertificateStore,
+};
+
+mod probe;
+use probe::{probe_origins, probe_roots, CANDIDATE_ROOTS, PROBE_TIMEOUT};
+
+mod dns;
+
+pub mod interface;
+pub use interface::{network_interfaces, NetworkInterface};
 
-const SERVICE_NAME: &str = "_uscan._tcp.local";
+pub mod discovery;
+pub use discovery::{
+    discover, discover_diagnostics, discover_on, discover_stream, discover_stream_on,
+    discover_wide_area, DiscoverError, DiscoveryEvent, DiscoveryReport, InterfaceSelector,
+    Interfaces, RejectionReason, ScannerService, ServiceDiagnostics, TxtRecord,
+};
+
+pub mod sweep;
+pub use sweep::Sweep;
+
+#[cfg(test)]
+mod test_server;
 
 #[derive(Debug)]
 pub struct Scanner {
-    base_url: Url,
+    /// Base URLs of the same scanner, tried in order when connecting fails.
+    base_urls: Vec<Url>,
+    /// Index into `base_urls` of the URL that last accepted a connection.
+    active_url: AtomicUsize,
     http_client: Client,
+    retry_policy: RetryPolicy,
+    authenticator: Option<Arc<Authenticator>>,
+    pinning: Option<CertificatePinning>,
+}
+
+/// Builder for [Scanner] instances with custom HTTP client settings.
+///
+/// A single [Client] can be shared across many [Scanner]s (and the [ScanJob]s they create) with
+/// [ScannerBuilder::http_client], in which case they all use the same connection pool.
+pub struct ScannerBuilder {
+    base_urls: Vec<Url>,
+    http_client: Option<Client>,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    user_agent: Option<String>,
+    proxies: Vec<reqwest::Proxy>,
+    root_certificates: Vec<reqwest::Certificate>,
+    accept_invalid_certs: bool,
+    retry_policy: RetryPolicy,
+    credentials: Option<Credentials>,
+    certificate_store: Option<Arc<dyn CertificateStore>>,
+    scanner_uuid: Option<String>,
+    /// Host names standing for IPv6 link-local addresses in base URLs
+    link_local_hosts: Vec<(String, SocketAddr)>,
 }
 
 #[derive(Debug)]
 pub enum Error {
     Http(reqwest::Error),
     Xml(serde_xml_rs::Error),
-    UnexpectedStatusCode(StatusCode),
+    /// `409 Conflict`. The scanner rejected the request in its current form, typically because
+    /// the scan settings are invalid or the document format is unsupported.
+    Conflict(Box<ErrorResponse>),
+    /// `503 Service Unavailable`. The scanner is busy or the requested page is not ready yet. The
+    /// request can be retried later.
+    ServiceUnavailable(Box<ErrorResponse>),
+    /// `404 Not Found`. The resource (e.g. a scan job) does not exist on the scanner.
+    NotFound(Box<ErrorResponse>),
+    /// `401 Unauthorized` or `403 Forbidden`. The scanner requires authentication and no
+    /// [Credentials] were supplied, the supplied credentials were rejected, or they are not
+    /// permitted to access the resource.
+    Unauthorized(Box<ErrorResponse>),
+    /// `5xx` status codes other than `503 Service Unavailable`.
+    ServerError(Box<ErrorResponse>),
+    /// Any other status code not expected for the request.
+    UnexpectedStatusCode(Box<ErrorResponse>),
     LocationHeader,
+    Io(std::io::Error),
+    /// The host passed to [Scanner::probe] cannot be turned into a URL.
+    InvalidHost(String),
+    /// The scanner presented a TLS certificate different from the one pinned for its `UUID`.
+    CertificateMismatch {
+        uuid: String,
+        expected: CertificateFingerprint,
+        actual: CertificateFingerprint,
+    },
+    /// No certificate is pinned yet, see [ScannerBuilder::certificate_pinning].
+    CertificateNotPinned,
+}
+
+/// Details of an HTTP response with a status code indicating failure.
+#[derive(Debug, Clone)]
+pub struct ErrorResponse {
+    /// URL of the request that failed
+    pub url: Url,
+    pub status_code: StatusCode,
+    /// Response body as returned by the scanner. Left empty if the body cannot be read.
+    pub body: String,
+    /// Delay requested by the scanner via the `Retry-After` header
+    pub retry_after: Option<Duration>,
 }
 
 #[derive(Debug)]
 pub struct ScanJob {
     job_url: Url,
     http_client: Client,
+    retry_policy: RetryPolicy,
+    authenticator: Option<Arc<Authenticator>>,
+    pinning: Option<CertificatePinning>,
+    duplex: bool,
 }
 
-#[derive(Debug)]
-pub struct ScannerService {
-    base_url: Url,
-    name: String,
-}
-
-#[derive(Debug)]
-pub enum DiscoverError {
-    Mdns(mdns::Error),
+/// Outcome of a [ScanJob::cancel] request, as reported by the scanner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelOutcome {
+    /// The scanner accepted the cancellation (`200 OK`). The job should transition into
+    /// [JobState::Canceled](status::JobState::Canceled), which can be confirmed with
+    /// [Scanner::status] and [ScannerStatus::find_job].
+    Canceled,
+    /// The scanner does not know about the job (`404 Not Found`). This usually means the job has
+    /// already ended and been removed from the job history.
+    NotFound,
+    /// The job cannot be canceled in its current state (`409 Conflict`). This usually means the
+    /// job has already completed or been canceled.
+    Conflict,
 }
 
 impl Scanner {
@@ -62,78 +184,560 @@ impl Scanner {
     /// ```
     pub fn new(base_url: Url) -> Self {
         Self {
-            base_url,
+            base_urls: vec![base_url],
+            active_url: AtomicUsize::new(0),
             http_client: Client::new(),
+            retry_policy: RetryPolicy::default(),
+            authenticator: None,
+            pinning: None,
+        }
+    }
+
+    /// Creates a new [Scanner] instance that sends requests with an existing HTTP client. Cloning
+    /// a [Client] is cheap, and clones share the same connection pool.
+    pub fn with_client(base_url: Url, http_client: Client) -> Self {
+        Self {
+            base_urls: vec![base_url],
+            active_url: AtomicUsize::new(0),
+            http_client,
+            retry_policy: RetryPolicy::default(),
+            authenticator: None,
+            pinning: None,
         }
     }
 
+    /// Finds the base URL of a scanner from a manually entered `host`, such as `192.168.1.1`,
+    /// `scanner.local:8080` or `https://192.168.1.1`.
+    ///
+    /// The common eSCL roots (`/eSCL`, `/escl` and `/`) are probed over HTTP, then HTTPS, unless
+    /// `host` includes a scheme. A root is accepted once it serves a valid `ScannerCapabilities`
+    /// document, which is returned along with the [Scanner]. Redirects, such as from HTTP to
+    /// HTTPS, are followed and the base URL is taken from the final location. Each probing request
+    /// times out after 5 seconds. The HTTPS certificate gets pinned for the returned [Scanner], as
+    /// with [ScannerBuilder::certificate_pinning].
+    ///
+    /// ```no_run
+    /// # async fn run() {
+    /// use escl::Scanner;
+    ///
+    /// let (scanner, capabilities) = Scanner::probe("192.168.1.1").await.unwrap();
+    /// println!("{} at {}", capabilities.make_and_model, scanner.base_url());
+    /// # }
+    /// ```
+    pub async fn probe(host: &str) -> Result<(Self, ScannerCapabilities), Error> {
+        Self::probe_with_pinning(host, Arc::new(MemoryCertificateStore::default())).await
+    }
+
+    /// Same as [Scanner::probe], but checks the HTTPS certificate against, or records it into,
+    /// `store`. Fails with [Error::CertificateMismatch] if the scanner presents a certificate
+    /// different from the one stored for its `UUID`.
+    pub async fn probe_with_pinning(
+        host: &str,
+        store: Arc<dyn CertificateStore>,
+    ) -> Result<(Self, ScannerCapabilities), Error> {
+        let pinning = CertificatePinning::new(store, None);
+        let probe_client = pinning
+            .client_builder()
+            .connect_timeout(PROBE_TIMEOUT)
+            .timeout(PROBE_TIMEOUT)
+            .build()
+            .map_err(Error::Http)?;
+        // Transferring documents can take much longer than probing
+        let http_client = pinning.client_builder().build().map_err(Error::Http)?;
+
+        let (mut scanner, capabilities) = Self::probe_with_client(host, probe_client).await?;
+        pinning.check(&capabilities.uuid)?;
+        scanner.http_client = http_client;
+        scanner.pinning = Some(pinning);
+
+        Ok((scanner, capabilities))
+    }
+
+    /// Same as [Scanner::probe], but sends requests with an existing HTTP client, which verifies
+    /// certificates as configured, including after a redirect from HTTP to HTTPS.
+    pub async fn probe_with_client(
+        host: &str,
+        http_client: Client,
+    ) -> Result<(Self, ScannerCapabilities), Error> {
+        let mut last_err = Error::InvalidHost(host.to_owned());
+        for origin in probe_origins(host) {
+            // An explicitly entered path is tried first
+            let path = origin.path().trim_matches('/').to_owned();
+            let roots = std::iter::once(path.as_str())
+                .filter(|path| !path.is_empty())
+                .chain(CANDIDATE_ROOTS);
+
+            match probe_roots(&http_client, &origin, roots).await {
+                Ok((base_url, capabilities)) => {
+                    return Ok((Self::with_client(base_url, http_client), capabilities))
+                }
+                // No point in trying another scheme with credentials missing
+                Err(err @ Error::Unauthorized(_)) => return Err(err),
+                Err(err) => last_err = err,
+            }
+        }
+
+        Err(last_err)
+    }
+
+    /// Creates a [ScannerBuilder] for customizing the HTTP client used by the scanner.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use escl::{Scanner, Url};
+    ///
+    /// let scanner = Scanner::builder(Url::parse("https://192.168.1.1/eSCL").unwrap())
+    ///     .connect_timeout(Duration::from_secs(5))
+    ///     .user_agent("my-app/1.0")
+    ///     .danger_accept_invalid_certs(true)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn builder(base_url: Url) -> ScannerBuilder {
+        ScannerBuilder::new(base_url)
+    }
+
+    /// Base URL currently used to reach the scanner. When several base URLs are known (e.g. for
+    /// a [ScannerService] with both IPv4 and IPv6 addresses), this is the one that last accepted
+    /// a connection.
+    pub fn base_url(&self) -> &Url {
+        &self.base_urls[self.active_url.load(Ordering::Relaxed)]
+    }
+
+    /// Replaces the [RetryPolicy] used by this scanner and the [ScanJob]s it creates.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Authenticates requests sent by this scanner and the [ScanJob]s it creates with
+    /// `credentials`, for scanners that require HTTP authentication.
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.authenticator = Some(Arc::new(Authenticator::new(credentials)));
+        self
+    }
+
+    /// Fetches the scanner capabilities. When certificate pinning is enabled, this is also when
+    /// the certificate presented by the scanner gets checked against, or recorded into, the
+    /// [CertificateStore].
     pub async fn capabilities(&self) -> Result<ScannerCapabilities, Error> {
-        self.send_get_request(self.extended_url(&["ScannerCapabilities"]))
-            .await
+        let capabilities: ScannerCapabilities =
+            self.send_get_request(&["ScannerCapabilities"]).await?;
+
+        if let Some(pinning) = &self.pinning {
+            pinning.check(&capabilities.uuid)?;
+        }
+
+        Ok(capabilities)
     }
 
+    /// Fetches the scanner status. Job URIs are resolved into [JobInfo::job_url](status::JobInfo)
+    /// against the scanner's base URL.
     pub async fn status(&self) -> Result<ScannerStatus, Error> {
-        self.send_get_request(self.extended_url(&["ScannerStatus"]))
-            .await
+        self.ensure_pinned()?;
+
+        let mut status: ScannerStatus = self.send_get_request(&["ScannerStatus"]).await?;
+        status.resolve_job_urls(&extend_url(self.base_url(), &["ScannerStatus"]));
+
+        Ok(status)
     }
 
+    /// Submits a scan job. Requests rejected with `503 Service Unavailable` because the scanner
+    /// is busy are retried according to the scanner's [RetryPolicy].
     pub async fn scan(&self, settings: &ScanSettings) -> Result<ScanJob, Error> {
-        let url = self.extended_url(&["ScanJobs"]);
+        self.ensure_pinned()?;
 
         let request_body = serde_xml_rs::to_string(settings).map_err(Error::Xml)?;
 
+        // Only 503 is retried: on other failures, such as timeouts, the job might have been
+        // created already.
         let response = self
-            .http_client
-            .post(url)
-            .header("Content-Type", "text/xml")
-            .body(request_body)
-            .send()
-            .await
-            .map_err(Error::Http)?;
+            .retry_policy
+            .run(
+                || self.send_scan_request(&request_body),
+                |err| matches!(err, Error::ServiceUnavailable(_)),
+            )
+            .await?;
 
-        let status_code = response.status();
-        if status_code != StatusCode::CREATED {
-            return Err(Error::UnexpectedStatusCode(status_code));
-        }
-
-        let location: Url = response
+        let location = response
             .headers()
             .get("location")
             .ok_or(Error::LocationHeader)?
             .to_str()
-            .map_err(|_| Error::LocationHeader)?
-            .parse()
             .map_err(|_| Error::LocationHeader)?;
+        let location = resolve_url(response.url(), location).ok_or(Error::LocationHeader)?;
 
         Ok(ScanJob {
             job_url: location,
             http_client: self.http_client.clone(),
+            retry_policy: self.retry_policy.clone(),
+            authenticator: self.authenticator.clone(),
+            pinning: self.pinning.clone(),
+            duplex: settings.duplex == Some(true),
         })
     }
 
-    fn extended_url(&self, segments: &[&'static str]) -> Url {
-        let mut url = self.base_url.clone();
-        url.path_segments_mut()
-            .expect("Invalid base URL")
-            .extend(segments);
+    async fn send_scan_request(&self, request_body: &str) -> Result<reqwest::Response, Error> {
+        let response = self
+            .with_base_url(&["ScanJobs"], |url| {
+                send_request(
+                    &self.http_client,
+                    self.authenticator.as_deref(),
+                    self.pinning.as_ref(),
+                    Method::POST,
+                    url,
+                    Some(request_body),
+                )
+            })
+            .await?;
+
+        if response.status() != StatusCode::CREATED {
+            return Err(Error::from_response(response).await);
+        }
+
+        Ok(response)
+    }
+
+    /// Sends `request` to the resource at `segments`, trying each base URL in turn, starting with
+    /// the one that last worked, until a connection can be established.
+    async fn with_base_url<T, F, Fut>(
+        &self,
+        segments: &[&'static str],
+        request: F,
+    ) -> Result<T, Error>
+    where
+        F: Fn(Url) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let start = self.active_url.load(Ordering::Relaxed);
+        let mut last_err = None;
+
+        for offset in 0..self.base_urls.len() {
+            let ind = (start + offset) % self.base_urls.len();
+
+            match request(extend_url(&self.base_urls[ind], segments)).await {
+                Err(Error::Http(err)) if err.is_connect() => last_err = Some(Error::Http(err)),
+                result => {
+                    self.active_url.store(ind, Ordering::Relaxed);
+                    return result;
+                }
+            }
+        }
 
-        url
+        Err(last_err.expect("Scanner without base URL"))
     }
 
-    async fn send_get_request<T>(&self, url: Url) -> Result<T, Error>
+    async fn send_get_request<T>(&self, segments: &[&'static str]) -> Result<T, Error>
     where
         T: DeserializeOwned,
     {
-        let response = self
-            .http_client
-            .get(url)
-            .send()
-            .await
-            .map_err(Error::Http)?;
+        self.with_base_url(segments, |url| {
+            send_get_request(
+                &self.http_client,
+                self.authenticator.as_deref(),
+                self.pinning.as_ref(),
+                url,
+            )
+        })
+        .await
+    }
+
+    /// Refuses to talk to a scanner over HTTPS until its certificate is pinned, when pinning is
+    /// enabled. Only [Scanner::capabilities] may be sent before, as it's what pins the certificate.
+    fn ensure_pinned(&self) -> Result<(), Error> {
+        match &self.pinning {
+            Some(pinning)
+                if !pinning.is_pinned()
+                    && self.base_urls.iter().any(|url| url.scheme() == "https") =>
+            {
+                Err(Error::CertificateNotPinned)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl ScannerBuilder {
+    pub fn new(base_url: Url) -> Self {
+        Self {
+            base_urls: vec![base_url],
+            http_client: None,
+            connect_timeout: None,
+            timeout: None,
+            user_agent: None,
+            proxies: vec![],
+            root_certificates: vec![],
+            accept_invalid_certs: false,
+            retry_policy: RetryPolicy::default(),
+            credentials: None,
+            certificate_store: None,
+            scanner_uuid: None,
+            link_local_hosts: vec![],
+        }
+    }
+
+    /// Creates a builder for a discovered scanner, reached over HTTPS if advertised via
+    /// `_uscans._tcp`, and plain HTTP otherwise. Only URLs of that scheme are used, so that HTTPS
+    /// never falls back to plain HTTP. The advertised `UUID` is set as
+    /// [ScannerBuilder::scanner_uuid].
+    ///
+    /// ```no_run
+    /// # async fn run() {
+    /// use std::{sync::Arc, time::Duration};
+    ///
+    /// use escl::{discover, pinning::MemoryCertificateStore, ScannerBuilder};
+    ///
+    /// let services = discover(Duration::from_secs(5)).await.unwrap();
+    /// let scanner = ScannerBuilder::from_service(&services[0])
+    ///     .certificate_pinning(Arc::new(MemoryCertificateStore::default()))
+    ///     .build()
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn from_service(service: &ScannerService) -> Self {
+        let scheme = if service.is_tls() { "https" } else { "http" };
+        let mut base_urls = service_urls(service, scheme).into_iter();
+
+        let mut builder = Self::new(base_urls.next().unwrap_or_else(|| service.url().clone()));
+        builder.base_urls.extend(base_urls);
+        builder.scanner_uuid = service.txt().uuid.clone();
+        builder.link_local_hosts = service.link_local_hosts();
+
+        builder
+    }
+
+    /// Adds another base URL of the same scanner (e.g. an IPv6 address of a scanner also reachable
+    /// over IPv4), to be tried when connecting to the previous ones fails.
+    pub fn fallback_url(mut self, base_url: Url) -> Self {
+        self.base_urls.push(base_url);
+        self
+    }
+
+    /// Uses an existing HTTP client instead of building a new one. When set, all the other HTTP
+    /// options of the builder are ignored, as they must be configured on the client itself. This
+    /// includes the host names of IPv6 link-local addresses set up by
+    /// [ScannerBuilder::from_service], see [ScannerService::urls].
+    pub fn http_client(mut self, http_client: Client) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    /// Timeout for establishing connections to the scanner.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout for each request, from sending the request until the response body has been fully
+    /// received. Keep in mind that downloading a high resolution page can take a long time.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn user_agent<S>(mut self, user_agent: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
 
-        let response_body = response.text().await.map_err(Error::Http)?;
+    /// Adds a proxy to route requests through. Can be called multiple times.
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// Trusts an additional root certificate, such as the scanner's own self-signed certificate.
+    pub fn add_root_certificate(mut self, certificate: reqwest::Certificate) -> Self {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    /// Disables TLS certificate validation altogether, leaving the connection open to
+    /// man-in-the-middle attacks. Prefer [ScannerBuilder::certificate_pinning].
+    pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Credentials for scanners that require HTTP authentication.
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Enables trust-on-first-use certificate pinning, since scanners mostly use self-signed
+    /// certificates that cannot be validated otherwise. Takes precedence over
+    /// [ScannerBuilder::http_client], [ScannerBuilder::add_root_certificate] and
+    /// [ScannerBuilder::danger_accept_invalid_certs].
+    ///
+    /// [Scanner::capabilities] pins the certificate into `store` under the scanner `UUID`, and
+    /// other certificates are rejected with [Error::CertificateMismatch] from then on. Until then,
+    /// other requests and authentication challenges fail with [Error::CertificateNotPinned],
+    /// unless a certificate is already stored for [ScannerBuilder::scanner_uuid].
+    pub fn certificate_pinning(mut self, store: Arc<dyn CertificateStore>) -> Self {
+        self.certificate_store = Some(store);
+        self
+    }
 
-        serde_xml_rs::from_str(&response_body).map_err(Error::Xml)
+    /// `UUID` of the scanner, if already known (e.g. from a previous session). This allows a
+    /// previously pinned certificate to be enforced from the very first connection.
+    pub fn scanner_uuid<S>(mut self, uuid: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.scanner_uuid = Some(uuid.into());
+        self
+    }
+
+    pub fn build(self) -> Result<Scanner, Error> {
+        let pinning = self
+            .certificate_store
+            .map(|store| CertificatePinning::new(store, self.scanner_uuid.as_deref()));
+
+        let http_client = match (self.http_client, &pinning) {
+            (Some(http_client), None) => http_client,
+            _ => {
+                let mut builder = match &pinning {
+                    Some(pinning) => pinning.client_builder(),
+                    None => {
+                        let mut builder = Client::builder()
+                            .danger_accept_invalid_certs(self.accept_invalid_certs);
+                        for certificate in self.root_certificates {
+                            builder = builder.add_root_certificate(certificate);
+                        }
+                        builder
+                    }
+                };
+
+                for (host, address) in self.link_local_hosts.iter() {
+                    builder = builder.resolve(host, *address);
+                }
+                if let Some(timeout) = self.connect_timeout {
+                    builder = builder.connect_timeout(timeout);
+                }
+                if let Some(timeout) = self.timeout {
+                    builder = builder.timeout(timeout);
+                }
+                if let Some(user_agent) = self.user_agent {
+                    builder = builder.user_agent(user_agent);
+                }
+                for proxy in self.proxies {
+                    builder = builder.proxy(proxy);
+                }
+
+                builder.build().map_err(Error::Http)?
+            }
+        };
+
+        Ok(Scanner {
+            base_urls: self.base_urls,
+            active_url: AtomicUsize::new(0),
+            http_client,
+            retry_policy: self.retry_policy,
+            authenticator: self
+                .credentials
+                .map(|credentials| Arc::new(Authenticator::new(credentials))),
+            pinning,
+        })
+    }
+}
+
+impl std::fmt::Debug for ScannerBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScannerBuilder")
+            .field("base_urls", &self.base_urls)
+            .field("http_client", &self.http_client)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("timeout", &self.timeout)
+            .field("user_agent", &self.user_agent)
+            .field("proxies", &self.proxies)
+            .field("accept_invalid_certs", &self.accept_invalid_certs)
+            .field("retry_policy", &self.retry_policy)
+            .field("credentials", &self.credentials)
+            .field("scanner_uuid", &self.scanner_uuid)
+            .field("link_local_hosts", &self.link_local_hosts)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Error {
+    /// Whether the failed operation may succeed if retried later without any change. This is the
+    /// case for scanners being busy, transient server errors, and network timeouts.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Http(err) => err.is_timeout() || err.is_connect(),
+            Error::ServiceUnavailable(_) => true,
+            Error::ServerError(response) => matches!(
+                response.status_code,
+                StatusCode::BAD_GATEWAY | StatusCode::GATEWAY_TIMEOUT
+            ),
+            Error::Xml(_)
+            | Error::Conflict(_)
+            | Error::NotFound(_)
+            | Error::Unauthorized(_)
+            | Error::UnexpectedStatusCode(_)
+            | Error::LocationHeader
+            | Error::Io(_)
+            | Error::InvalidHost(_)
+            | Error::CertificateMismatch { .. }
+            | Error::CertificateNotPinned => false,
+        }
+    }
+
+    /// Returns the failed HTTP response details, if the error was caused by a failure status code.
+    pub fn response(&self) -> Option<&ErrorResponse> {
+        match self {
+            Error::Conflict(response)
+            | Error::ServiceUnavailable(response)
+            | Error::NotFound(response)
+            | Error::Unauthorized(response)
+            | Error::ServerError(response)
+            | Error::UnexpectedStatusCode(response) => Some(response),
+            Error::Http(_)
+            | Error::Xml(_)
+            | Error::LocationHeader
+            | Error::Io(_)
+            | Error::InvalidHost(_)
+            | Error::CertificateMismatch { .. }
+            | Error::CertificateNotPinned => None,
+        }
+    }
+
+    /// Classifies a response with an unexpected status code into the matching error variant.
+    async fn from_response(response: reqwest::Response) -> Self {
+        let url = response.url().clone();
+        let status_code = response.status();
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(retry::parse_retry_after);
+        let body = response.text().await.unwrap_or_default();
+
+        let error_response = Box::new(ErrorResponse {
+            url,
+            status_code,
+            body,
+            retry_after,
+        });
+
+        match status_code {
+            StatusCode::CONFLICT => Error::Conflict(error_response),
+            StatusCode::SERVICE_UNAVAILABLE => Error::ServiceUnavailable(error_response),
+            StatusCode::NOT_FOUND => Error::NotFound(error_response),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Error::Unauthorized(error_response),
+            status_code if status_code.is_server_error() => Error::ServerError(error_response),
+            _ => Error::UnexpectedStatusCode(error_response),
+        }
     }
 }
 
@@ -142,189 +746,575 @@ impl Display for Error {
         match self {
             Error::Http(err) => write!(f, "http error: {}", err),
             Error::Xml(err) => write!(f, "xml error: {}", err),
-            Error::UnexpectedStatusCode(code) => write!(f, "unexpected http status code {}", code),
+            Error::Conflict(response) => write!(f, "conflict: {}", response),
+            Error::ServiceUnavailable(response) => {
+                write!(f, "scanner unavailable: {}", response)
+            }
+            Error::NotFound(response) => write!(f, "not found: {}", response),
+            Error::Unauthorized(response) => write!(f, "unauthorized: {}", response),
+            Error::ServerError(response) => write!(f, "scanner error: {}", response),
+            Error::UnexpectedStatusCode(response) => {
+                write!(f, "unexpected http status code: {}", response)
+            }
             Error::LocationHeader => write!(f, "missing or invalid `Location` header in response"),
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::InvalidHost(host) => write!(f, "invalid scanner host: {}", host),
+            Error::CertificateMismatch {
+                uuid,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "certificate of scanner {} changed: expected {}, got {}",
+                uuid, expected, actual
+            ),
+            Error::CertificateNotPinned => write!(f, "scanner certificate not pinned yet"),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+impl Display for ErrorResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} returned by {}", self.status_code, self.url)
+    }
+}
+
 impl ScanJob {
-    pub async fn next_document(&self) -> Result<Option<Vec<u8>>, Error> {
-        let url = self.extended_url(&["NextDocument"]);
+    /// Downloads the next scanned document of the job. `None` is returned when there are no more
+    /// documents to retrieve.
+    ///
+    /// Pages that are not ready yet are waited for according to the job's [RetryPolicy].
+    pub async fn next_document(&self) -> Result<Option<Document>, Error> {
+        match self.send_next_document_request().await? {
+            Some(response) => Ok(Some(Document::from_response(response).await?)),
+            None => Ok(None),
+        }
+    }
 
-        let response = self
-            .http_client
-            .get(url)
-            .send()
+    /// Same as [ScanJob::next_document], except that the document body is streamed instead of
+    /// being buffered in memory.
+    pub async fn next_document_stream(&self) -> Result<Option<DocumentStream>, Error> {
+        match self.send_next_document_request().await? {
+            Some(response) => Ok(Some(DocumentStream::from_response(response).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Downloads all remaining documents of the job. For duplex jobs, pages delivered in
+    /// `page_order` are rearranged into reading order, see [DuplexPageOrder::reorder].
+    pub async fn documents(&self, page_order: DuplexPageOrder) -> Result<Vec<Document>, Error> {
+        let mut documents = Vec::new();
+        while let Some(document) = self.next_document().await? {
+            documents.push(document);
+        }
+
+        if self.duplex {
+            documents = page_order.reorder(documents);
+        }
+
+        Ok(documents)
+    }
+
+    /// Fetches information about the last image produced by the job, including its actual
+    /// dimensions. This is only available on scanners implementing eSCL 2.x and later, and should
+    /// be called after a document has been retrieved with [ScanJob::next_document].
+    pub async fn image_info(&self) -> Result<ScanImageInfo, Error> {
+        send_get_request(
+            &self.http_client,
+            self.authenticator.as_deref(),
+            self.pinning.as_ref(),
+            self.extended_url(&["ScanImageInfo"]),
+        )
+        .await
+    }
+
+    /// Cancels the job by sending a `DELETE` request on the job URL.
+    ///
+    /// Status codes defined by the eSCL specification are mapped into [CancelOutcome]. Any other
+    /// status code results in an error.
+    pub async fn cancel(&self) -> Result<CancelOutcome, Error> {
+        let response = send_request(
+            &self.http_client,
+            self.authenticator.as_deref(),
+            self.pinning.as_ref(),
+            Method::DELETE,
+            self.job_url.clone(),
+            None,
+        )
+        .await?;
+
+        match response.status() {
+            StatusCode::OK => Ok(CancelOutcome::Canceled),
+            StatusCode::NOT_FOUND => Ok(CancelOutcome::NotFound),
+            StatusCode::CONFLICT => Ok(CancelOutcome::Conflict),
+            _ => Err(Error::from_response(response).await),
+        }
+    }
+
+    async fn send_next_document_request(&self) -> Result<Option<reqwest::Response>, Error> {
+        // Only 503 is retried, as it's how scanners signal that the page is not ready yet. Other
+        // failures might have happened after the page was consumed.
+        self.retry_policy
+            .run(
+                || self.try_send_next_document_request(),
+                |err| matches!(err, Error::ServiceUnavailable(_)),
+            )
             .await
-            .map_err(Error::Http)?;
+    }
+
+    async fn try_send_next_document_request(&self) -> Result<Option<reqwest::Response>, Error> {
+        let response = send_request(
+            &self.http_client,
+            self.authenticator.as_deref(),
+            self.pinning.as_ref(),
+            Method::GET,
+            self.extended_url(&["NextDocument"]),
+            None,
+        )
+        .await?;
 
         let status_code = response.status();
         if status_code == StatusCode::NOT_FOUND {
             return Ok(None);
         } else if status_code != StatusCode::OK {
-            return Err(Error::UnexpectedStatusCode(status_code));
+            return Err(Error::from_response(response).await);
         }
 
-        let bytes = response.bytes().await.map_err(Error::Http)?;
-        Ok(Some(bytes.to_vec()))
+        Ok(Some(response))
     }
 
     fn extended_url(&self, segments: &[&'static str]) -> Url {
-        let mut url = self.job_url.clone();
-        url.path_segments_mut()
-            .expect("Invalid base URL")
-            .extend(segments);
-
-        url
+        extend_url(&self.job_url, segments)
     }
 
     pub fn job_url(&self) -> &Url {
         &self.job_url
     }
-}
-
-impl ScannerService {
-    /// Base URL that can be used to initialize a [Scanner] instance
-    pub fn url(&self) -> &Url {
-        &self.base_url
-    }
 
-    /// Human readable scanner make and model
-    pub fn name(&self) -> &str {
-        &self.name
+    /// Whether the job was submitted with [ScanSettings::duplex] enabled.
+    pub fn is_duplex(&self) -> bool {
+        self.duplex
     }
 }
 
 impl From<&ScannerService> for Scanner {
+    /// Creates a scanner reaching the service over plain HTTP, which works without any certificate
+    /// setup. HTTPS is only used for services not advertised over plain HTTP at all, see
+    /// [ScannerBuilder::from_service] for setting it up.
     fn from(value: &ScannerService) -> Self {
+        let mut base_urls = service_urls(value, "http");
+        if base_urls.is_empty() {
+            base_urls = value.urls().to_vec();
+        }
+
+        let mut http_client = Client::builder();
+        for (host, address) in value.link_local_hosts() {
+            http_client = http_client.resolve(&host, address);
+        }
+
         Self {
-            base_url: value.base_url.clone(),
-            http_client: Client::new(),
+            base_urls,
+            active_url: AtomicUsize::new(0),
+            // Same as `Client::new`
+            http_client: http_client.build().expect("Client::new()"),
+            retry_policy: RetryPolicy::default(),
+            authenticator: None,
+            pinning: None,
         }
     }
 }
 
 impl From<ScannerService> for Scanner {
     fn from(value: ScannerService) -> Self {
-        Self {
-            base_url: value.base_url,
-            http_client: Client::new(),
-        }
+        Self::from(&value)
     }
 }
 
-impl Display for DiscoverError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Mdns(err) => write!(f, "mDNS error: {}", err),
+/// Base URLs of `service` with the given scheme, in order of preference.
+fn service_urls(service: &ScannerService, scheme: &str) -> Vec<Url> {
+    service
+        .urls()
+        .iter()
+        .filter(|url| url.scheme() == scheme)
+        .cloned()
+        .collect()
+}
+
+/// Resolves a URL reported by the scanner, which may be relative, against the URL of the request
+/// it was reported in.
+///
+/// Only the path and query are taken from `reference`. Scheme, host and port always remain those
+/// of `base`, as some firmwares report absolute URLs with an internal host name, a different port,
+/// or `http` while being accessed over HTTPS.
+pub(crate) fn resolve_url(base: &Url, reference: &str) -> Option<Url> {
+    let resolved = base.join(reference.trim()).ok()?;
+
+    let mut url = base.clone();
+    url.set_path(resolved.path());
+    url.set_query(resolved.query());
+    url.set_fragment(None);
+
+    Some(url)
+}
+
+fn extend_url(base_url: &Url, segments: &[&'static str]) -> Url {
+    let mut url = base_url.clone();
+    url.path_segments_mut()
+        .expect("Invalid base URL")
+        .extend(segments);
+
+    url
+}
+
+async fn send_get_request<T>(
+    http_client: &Client,
+    authenticator: Option<&Authenticator>,
+    pinning: Option<&CertificatePinning>,
+    url: Url,
+) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    let response =
+        send_request(http_client, authenticator, pinning, Method::GET, url, None).await?;
+
+    if response.status() != StatusCode::OK {
+        return Err(Error::from_response(response).await);
+    }
+
+    let response_body = response.text().await.map_err(Error::Http)?;
+
+    serde_xml_rs::from_str(&response_body).map_err(Error::Xml)
+}
+
+/// Sends a request, answering at most one authentication challenge from the scanner when
+/// credentials are available. Certificates rejected by `pinning` are reported as such.
+async fn send_request(
+    http_client: &Client,
+    authenticator: Option<&Authenticator>,
+    pinning: Option<&CertificatePinning>,
+    method: Method,
+    url: Url,
+    body: Option<&str>,
+) -> Result<reqwest::Response, Error> {
+    let mut challenge_answered = false;
+
+    loop {
+        let mut request = http_client.request(method.clone(), url.clone());
+        if let Some(body) = body {
+            request = request
+                .header(CONTENT_TYPE, "text/xml")
+                .body(body.to_owned());
+        }
+        if let Some(authenticator) = authenticator {
+            request = authenticator.authenticate(request, &method, &url, body);
+        }
+
+        let response = request.send().await.map_err(|err| match pinning {
+            Some(pinning) => pinning.map_error(err),
+            None => Error::Http(err),
+        })?;
+
+        if response.status() == StatusCode::UNAUTHORIZED && !challenge_answered {
+            if let Some(authenticator) = authenticator {
+                // Credentials would be sent to whoever presented the certificate
+                if url.scheme() == "https" && pinning.is_some_and(|pinning| !pinning.is_pinned()) {
+                    return Err(Error::CertificateNotPinned);
+                }
+                if authenticator.handle_challenge(response.headers()) {
+                    challenge_answered = true;
+                    continue;
+                }
+            }
         }
+
+        return Ok(response);
     }
 }
 
-impl std::error::Error for DiscoverError {}
-
-/// Looks for eSCL-enabled scanner devices in LAN. Up to a set timeout.
-pub async fn discover(timeout: Duration) -> Result<Vec<ScannerService>, DiscoverError> {
-    let mdns_stream = mdns::discover::all(SERVICE_NAME, timeout)
-        .map_err(DiscoverError::Mdns)?
-        .listen();
-    pin_mut!(mdns_stream);
-
-    let services = match mdns_stream.next().await {
-        Some(Ok(response)) => {
-            response
-                .records()
-                .filter_map(|record| {
-                    if record.name == SERVICE_NAME {
-                        match &record.kind {
-                            RecordKind::PTR(ptr_record) => {
-                                // Each PTR record on the service name represents one scanner
-
-                                // There must be one TXT record with metadata
-                                let txt_record = response.records().find_map(|record| {
-                                    if &record.name == ptr_record {
-                                        match &record.kind {
-                                            RecordKind::TXT(txt) => Some(txt),
-                                            _ => None,
-                                        }
-                                    } else {
-                                        None
-                                    }
-                                })?;
-
-                                // Extracts URL prefix
-                                let rs = txt_record.iter().find_map(|item| {
-                                    let (key, value) = item.split_once('=')?;
-
-                                    if key == "rs" {
-                                        Some(value)
-                                    } else {
-                                        None
-                                    }
-                                })?;
-
-                                // Extracts human readable name
-                                let ty = txt_record.iter().find_map(|item| {
-                                    let (key, value) = item.split_once('=')?;
-
-                                    if key == "ty" {
-                                        Some(value)
-                                    } else {
-                                        None
-                                    }
-                                })?;
-
-                                // There must be one SRV record pointing to the address
-                                let (srv_record, port) = response.records().find_map(|record| {
-                                    if &record.name == ptr_record {
-                                        match &record.kind {
-                                            RecordKind::SRV { target, port, .. } => {
-                                                Some((target, port))
-                                            }
-                                            _ => None,
-                                        }
-                                    } else {
-                                        None
-                                    }
-                                })?;
-
-                                // There should be one A record with IP address
-                                let ip_addr = response.records().find_map(|record| {
-                                    if &record.name == srv_record {
-                                        match &record.kind {
-                                            RecordKind::A(ip_addr) => Some(ip_addr),
-                                            _ => None,
-                                        }
-                                    } else {
-                                        None
-                                    }
-                                })?;
-
-                                let url =
-                                    Url::parse(&format!("http://{}:{}/{}", ip_addr, port, rs))
-                                        .ok()?;
-
-                                Some(ScannerService {
-                                    base_url: url,
-                                    name: ty.to_owned(),
-                                })
-                            }
-                            _ => None,
-                        }
-                    } else {
-                        None
-                    }
-                })
-                .collect::<Vec<_>>()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    pub async fn test_cancel() {
+        let address = test_server::serve(|request| {
+            assert_eq!(request.method, "DELETE");
+            assert!(request.body.is_empty());
+            match request.path.as_str() {
+                "/eSCL/ScanJobs/1" => test_server::Response::new("200 OK"),
+                "/eSCL/ScanJobs/2" => test_server::Response::new("404 Not Found"),
+                "/eSCL/ScanJobs/3" => test_server::Response::new("409 Conflict"),
+                _ => test_server::Response::new("500 Internal Server Error")
+                    .header("Content-Type", "text/plain")
+                    .body("paper jam"),
+            }
+        })
+        .await;
+
+        let cancel = |job_id: &str| {
+            let job = ScanJob {
+                job_url: Url::parse(&format!("http://{}/eSCL/ScanJobs/{}", address, job_id))
+                    .unwrap(),
+                http_client: Client::new(),
+                retry_policy: RetryPolicy::default(),
+                authenticator: None,
+                pinning: None,
+                duplex: false,
+            };
+            async move { job.cancel().await }
+        };
+
+        assert_eq!(cancel("1").await.unwrap(), CancelOutcome::Canceled);
+        assert_eq!(cancel("2").await.unwrap(), CancelOutcome::NotFound);
+        assert_eq!(cancel("3").await.unwrap(), CancelOutcome::Conflict);
+        match cancel("4").await {
+            Err(Error::ServerError(response)) => assert_eq!(response.body, "paper jam"),
+            result => panic!("unexpected result: {:?}", result),
         }
-        Some(Err(err)) => return Err(DiscoverError::Mdns(err)),
-        _ => {
-            vec![]
+    }
+
+    #[tokio::test]
+    pub async fn test_error_from_response() {
+        let address = test_server::serve(|request| {
+            let status = match request.path.as_str() {
+                "/401" => "401 Unauthorized",
+                "/403" => "403 Forbidden",
+                "/404" => "404 Not Found",
+                "/409" => "409 Conflict",
+                "/500" => "500 Internal Server Error",
+                "/502" => "502 Bad Gateway",
+                "/503" => "503 Service Unavailable",
+                _ => "418 I'm a teapot",
+            };
+            test_server::Response::new(status).body(format!("error at {}", request.path))
+        })
+        .await;
+
+        let error = |path: &str| {
+            let url = Url::parse(&format!("http://{}{}", address, path)).unwrap();
+            async move {
+                let response = Client::new().get(url).send().await.unwrap();
+                Error::from_response(response).await
+            }
+        };
+
+        for (path, retryable) in [
+            ("/401", false),
+            ("/403", false),
+            ("/404", false),
+            ("/409", false),
+            ("/500", false),
+            ("/502", true),
+            ("/503", true),
+            ("/418", false),
+        ] {
+            let error = error(path).await;
+            match (path, &error) {
+                ("/401" | "/403", Error::Unauthorized(_))
+                | ("/404", Error::NotFound(_))
+                | ("/409", Error::Conflict(_))
+                | ("/500" | "/502", Error::ServerError(_))
+                | ("/503", Error::ServiceUnavailable(_))
+                | ("/418", Error::UnexpectedStatusCode(_)) => {}
+                _ => panic!("{} classified as {:?}", path, error),
+            }
+
+            let response = error.response().unwrap();
+            assert_eq!(response.status_code.as_str(), &path[1..]);
+            assert_eq!(response.url.path(), path);
+            assert_eq!(response.body, format!("error at {}", path));
+            assert_eq!(error.is_retryable(), retryable, "{}", path);
+        }
+
+        // Nothing listening on the port of a dropped listener
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_address = listener.local_addr().unwrap();
+        drop(listener);
+        let error = Client::new()
+            .get(format!("http://{}/", closed_address))
+            .send()
+            .await
+            .map_err(Error::Http)
+            .unwrap_err();
+        assert!(error.is_retryable());
+        assert!(error.response().is_none());
+
+        assert!(!Error::LocationHeader.is_retryable());
+        assert!(!Error::InvalidHost("host".to_owned()).is_retryable());
+    }
+
+    #[tokio::test]
+    pub async fn test_certificate_pinning() {
+        let handler = |request: &test_server::Request| match request.path.as_str() {
+            "/eSCL/ScannerCapabilities" => test_server::Response::new("200 OK")
+                .body(include_str!("../test-data/capabilities/canon_ts7450.xml")),
+            "/eSCL/ScannerStatus" => test_server::Response::new("200 OK")
+                .body(include_str!("../test-data/status/canceled_job.xml")),
+            _ => test_server::Response::new("404 Not Found"),
+        };
+        let scanner_address = test_server::serve_tls(
+            include_bytes!("../test-data/tls/cert1.der"),
+            include_bytes!("../test-data/tls/key1.der"),
+            handler,
+        )
+        .await;
+        let impostor_address = test_server::serve_tls(
+            include_bytes!("../test-data/tls/cert2.der"),
+            include_bytes!("../test-data/tls/key2.der"),
+            handler,
+        )
+        .await;
+
+        let store: Arc<dyn CertificateStore> = Arc::new(pinning::MemoryCertificateStore::default());
+        let build = |address: std::net::SocketAddr, uuid: Option<&str>| {
+            let mut builder =
+                Scanner::builder(Url::parse(&format!("https://{}/eSCL", address)).unwrap())
+                    .certificate_pinning(store.clone());
+            if let Some(uuid) = uuid {
+                builder = builder.scanner_uuid(uuid);
+            }
+            builder.build().unwrap()
+        };
+
+        // Nothing but the capabilities before the certificate is pinned
+        let scanner = build(scanner_address, None);
+        assert!(matches!(
+            scanner.status().await,
+            Err(Error::CertificateNotPinned)
+        ));
+        let uuid = scanner.capabilities().await.unwrap().uuid;
+        assert_eq!(
+            store.get(&uuid),
+            Some(CertificateFingerprint::from_der(include_bytes!(
+                "../test-data/tls/cert1.der"
+            )))
+        );
+        scanner.status().await.unwrap();
+
+        // Known scanner: the impostor is rejected during the handshake
+        let scanner = build(impostor_address, Some(&uuid));
+        match scanner.status().await {
+            Err(err @ Error::CertificateMismatch { .. }) => assert!(!err.is_retryable()),
+            result => panic!("unexpected result: {:?}", result),
         }
-    };
 
-    Ok(services)
+        // Unknown scanner: the impostor is only spotted from the capabilities, after which the
+        // stored certificate is enforced
+        let scanner = build(impostor_address, None);
+        assert!(matches!(
+            scanner.capabilities().await,
+            Err(Error::CertificateMismatch { .. })
+        ));
+        assert!(matches!(
+            scanner.capabilities().await,
+            Err(Error::CertificateMismatch { .. })
+        ));
+        assert!(matches!(
+            scanner.status().await,
+            Err(Error::CertificateMismatch { .. })
+        ));
+
+        // Impostor asking for credentials before anything is pinned
+        let credentials_sent = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let phishing_address = test_server::serve_tls(
+            include_bytes!("../test-data/tls/cert2.der"),
+            include_bytes!("../test-data/tls/key2.der"),
+            {
+                let credentials_sent = credentials_sent.clone();
+                move |request| {
+                    if request.header("Authorization").is_some() {
+                        credentials_sent.store(true, Ordering::Relaxed);
+                    }
+                    test_server::Response::new("401 Unauthorized")
+                        .header("WWW-Authenticate", "Basic realm=\"scanner\"")
+                }
+            },
+        )
+        .await;
+        let scanner =
+            Scanner::builder(Url::parse(&format!("https://{}/eSCL", phishing_address)).unwrap())
+                .certificate_pinning(store.clone())
+                .credentials(Credentials::new("user", "password"))
+                .build()
+                .unwrap();
+        assert!(matches!(
+            scanner.capabilities().await,
+            Err(Error::CertificateNotPinned)
+        ));
+        assert!(!credentials_sent.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    pub fn test_scanner_from_service() {
+        let capabilities: ScannerCapabilities =
+            serde_xml_rs::from_str(include_str!("../test-data/capabilities/canon_ts7450.xml"))
+                .unwrap();
+        let address = "192.168.1.10:443".parse().unwrap();
+        let mut service = ScannerService::from_capabilities(
+            Url::parse("https://192.168.1.10/eSCL").unwrap(),
+            address,
+            &capabilities,
+        );
+        service.merge(ScannerService::from_capabilities(
+            Url::parse("http://192.168.1.10/eSCL").unwrap(),
+            address,
+            &capabilities,
+        ));
+        assert!(service.is_tls());
+
+        let scanner = Scanner::from(&service);
+        assert_eq!(
+            scanner.base_urls,
+            [Url::parse("http://192.168.1.10/eSCL").unwrap()]
+        );
+
+        let builder = ScannerBuilder::from_service(&service);
+        assert_eq!(
+            builder.base_urls,
+            [Url::parse("https://192.168.1.10/eSCL").unwrap()]
+        );
+        assert_eq!(builder.scanner_uuid, Some(capabilities.uuid));
+    }
+
+    #[tokio::test]
+    pub async fn test_link_local_host() {
+        let address = test_server::serve(|request| match request.path.as_str() {
+            "/eSCL/ScannerCapabilities" => test_server::Response::new("200 OK")
+                .body(include_str!("../test-data/capabilities/canon_ts7450.xml")),
+            _ => test_server::Response::new("404 Not Found"),
+        })
+        .await;
+
+        // Stands in for a link-local address, which the loopback interface usually lacks
+        let host = "fe80--1s1.link-local.invalid";
+        let mut builder = Scanner::builder(
+            Url::parse(&format!("http://{}:{}/eSCL", host, address.port())).unwrap(),
+        );
+        builder.link_local_hosts = vec![(host.to_owned(), address)];
+
+        let capabilities = builder.build().unwrap().capabilities().await.unwrap();
+        assert_eq!(capabilities.make_and_model, "Canon TS7400 series");
+    }
+
+    #[test]
+    pub fn test_resolve_url() {
+        let base = Url::parse("https://192.168.1.1/eSCL/ScanJobs").unwrap();
+
+        for (reference, expected) in [
+            (
+                "/eSCL/ScanJobs/123",
+                "https://192.168.1.1/eSCL/ScanJobs/123",
+            ),
+            ("ScanJobs/123", "https://192.168.1.1/eSCL/ScanJobs/123"),
+            (
+                "http://localhost:8080/eSCL/ScanJobs/123",
+                "https://192.168.1.1/eSCL/ScanJobs/123",
+            ),
+        ] {
+            assert_eq!(resolve_url(&base, reference).unwrap().as_str(), expected);
+        }
+    }
 }
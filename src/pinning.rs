@@ -0,0 +1,390 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use rustls::{
+    client::{Resumption, ServerCertVerified, ServerCertVerifier},
+    Certificate, CertificateError, ServerName,
+};
+use sha2::{Digest, Sha256};
+
+use crate::Error;
+
+/// SHA-256 fingerprint of a DER-encoded TLS certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CertificateFingerprint([u8; 32]);
+
+/// Persistent storage of certificate fingerprints pinned for scanners, keyed by the scanner `UUID`
+/// found in [ScannerCapabilities](crate::capabilities::ScannerCapabilities).
+///
+/// Implement this trait to persist pins across process restarts (e.g. in a file or database).
+/// [MemoryCertificateStore] is provided for pins that only need to live as long as the process.
+pub trait CertificateStore: Send + Sync {
+    fn get(&self, uuid: &str) -> Option<CertificateFingerprint>;
+
+    fn put(&self, uuid: &str, fingerprint: CertificateFingerprint);
+}
+
+/// A [CertificateStore] keeping pins in memory.
+#[derive(Debug, Default)]
+pub struct MemoryCertificateStore {
+    fingerprints: Mutex<HashMap<String, CertificateFingerprint>>,
+}
+
+/// Certificate pinning state of a [Scanner](crate::Scanner).
+#[derive(Clone)]
+pub(crate) struct CertificatePinning {
+    store: Arc<dyn CertificateStore>,
+    verifier: Arc<PinningVerifier>,
+    /// `UUID` of the scanner, once known
+    uuid: Arc<Mutex<Option<String>>>,
+}
+
+/// Trust-on-first-use verifier. Any certificate is accepted until a fingerprint gets pinned, after
+/// which only the pinned certificate is accepted. The last certificate seen is kept so that it can
+/// be pinned once the scanner identity is known.
+#[derive(Debug, Default)]
+pub(crate) struct PinningVerifier {
+    state: Mutex<PinningState>,
+}
+
+#[derive(Debug, Default)]
+struct PinningState {
+    pinned: Option<CertificateFingerprint>,
+    last_seen: Option<CertificateFingerprint>,
+}
+
+/// Rejection of a certificate by [PinningVerifier], carried through the TLS error so that it can
+/// be told apart from other connection failures.
+#[derive(Debug)]
+struct PinMismatch {
+    expected: CertificateFingerprint,
+    actual: CertificateFingerprint,
+}
+
+impl CertificateFingerprint {
+    pub fn from_der(der: &[u8]) -> Self {
+        Self(Sha256::digest(der).into())
+    }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl Display for CertificateFingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (ind, byte) in self.0.iter().enumerate() {
+            if ind != 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl CertificateStore for MemoryCertificateStore {
+    fn get(&self, uuid: &str) -> Option<CertificateFingerprint> {
+        self.fingerprints
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(uuid)
+            .copied()
+    }
+
+    fn put(&self, uuid: &str, fingerprint: CertificateFingerprint) {
+        self.fingerprints
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(uuid.to_owned(), fingerprint);
+    }
+}
+
+impl CertificatePinning {
+    /// Creates the pinning state, pinning the certificate right away if the scanner `uuid` is
+    /// already known and has a stored fingerprint.
+    pub fn new(store: Arc<dyn CertificateStore>, uuid: Option<&str>) -> Self {
+        let verifier = Arc::new(PinningVerifier::default());
+        if let Some(fingerprint) = uuid.and_then(|uuid| store.get(uuid)) {
+            verifier.pin(fingerprint);
+        }
+
+        Self {
+            store,
+            verifier,
+            uuid: Arc::new(Mutex::new(uuid.map(str::to_owned))),
+        }
+    }
+
+    /// Whether a certificate is pinned, i.e. whether TLS connections are safe to use for anything
+    /// beyond identifying the scanner.
+    pub fn is_pinned(&self) -> bool {
+        self.verifier.pinned().is_some()
+    }
+
+    /// Starts an HTTP client verifying certificates against the pin. Connections are not kept
+    /// alive, so that each request goes through certificate verification.
+    pub fn client_builder(&self) -> reqwest::ClientBuilder {
+        reqwest::Client::builder()
+            .use_preconfigured_tls(self.tls_config())
+            .pool_max_idle_per_host(0)
+    }
+
+    fn tls_config(&self) -> rustls::ClientConfig {
+        let mut config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(self.verifier.clone())
+            .with_no_client_auth();
+
+        // Resumed sessions skip certificate verification
+        config.resumption = Resumption::disabled();
+
+        config
+    }
+
+    /// Checks the certificate last presented by the scanner against the fingerprint stored for
+    /// `uuid`, storing it if this is the first contact. The certificate is then pinned for all
+    /// subsequent connections.
+    ///
+    /// On mismatch, the stored certificate gets pinned instead, so that the impostor is rejected
+    /// from then on.
+    pub fn check(&self, uuid: &str) -> Result<(), Error> {
+        // Nothing to pin over plain HTTP
+        let actual = match self.verifier.last_seen() {
+            Some(fingerprint) => fingerprint,
+            None => return Ok(()),
+        };
+
+        *self.uuid.lock().unwrap_or_else(|err| err.into_inner()) = Some(uuid.to_owned());
+
+        match self.store.get(uuid) {
+            Some(expected) if expected != actual => {
+                self.verifier.pin(expected);
+                Err(Error::CertificateMismatch {
+                    uuid: uuid.to_owned(),
+                    expected,
+                    actual,
+                })
+            }
+            Some(_) => {
+                self.verifier.pin(actual);
+                Ok(())
+            }
+            None => {
+                self.store.put(uuid, actual);
+                self.verifier.pin(actual);
+                Ok(())
+            }
+        }
+    }
+
+    /// Turns a failed request into [Error::CertificateMismatch] if the certificate was rejected
+    /// for not matching the pinned one, instead of a mere connection failure.
+    pub fn map_error(&self, err: reqwest::Error) -> Error {
+        match find_mismatch(&err) {
+            Some(mismatch) => Error::CertificateMismatch {
+                uuid: self
+                    .uuid
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .clone()
+                    .unwrap_or_default(),
+                expected: mismatch.expected,
+                actual: mismatch.actual,
+            },
+            None => Error::Http(err),
+        }
+    }
+}
+
+impl std::fmt::Debug for CertificatePinning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertificatePinning")
+            .field("verifier", &self.verifier)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PinningVerifier {
+    pub fn pin(&self, fingerprint: CertificateFingerprint) {
+        self.state
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .pinned = Some(fingerprint);
+    }
+
+    pub fn pinned(&self) -> Option<CertificateFingerprint> {
+        self.state
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .pinned
+    }
+
+    pub fn last_seen(&self) -> Option<CertificateFingerprint> {
+        self.state
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .last_seen
+    }
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = CertificateFingerprint::from_der(&end_entity.0);
+
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        state.last_seen = Some(fingerprint);
+
+        match state.pinned {
+            Some(pinned) if pinned != fingerprint => Err(rustls::Error::InvalidCertificate(
+                CertificateError::Other(Arc::new(PinMismatch {
+                    expected: pinned,
+                    actual: fingerprint,
+                })),
+            )),
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+}
+
+impl Display for PinMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "certificate fingerprint {} does not match pinned fingerprint {}",
+            self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for PinMismatch {}
+
+/// Looks for a [PinMismatch] among the causes of `err`. TLS errors end up wrapped into (possibly
+/// nested) [std::io::Error]s, which don't expose what they wrap as their source.
+fn find_mismatch<'a>(err: &'a (dyn std::error::Error + 'static)) -> Option<&'a PinMismatch> {
+    let mut cause = Some(err);
+
+    while let Some(err) = cause {
+        if let Some(rustls::Error::InvalidCertificate(CertificateError::Other(err))) =
+            err.downcast_ref::<rustls::Error>()
+        {
+            return err.downcast_ref::<PinMismatch>();
+        }
+
+        cause = match err.downcast_ref::<std::io::Error>() {
+            Some(err) => err
+                .get_ref()
+                .map(|err| err as &(dyn std::error::Error + 'static)),
+            None => err.source(),
+        };
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_pinning_verifier() {
+        let verifier = PinningVerifier::default();
+        let server_name = ServerName::try_from("scanner.local").unwrap();
+
+        let verify = |der: &[u8]| {
+            verifier.verify_server_cert(
+                &Certificate(der.to_vec()),
+                &[],
+                &server_name,
+                &mut std::iter::empty(),
+                &[],
+                SystemTime::now(),
+            )
+        };
+
+        // Anything goes before pinning
+        assert!(verify(b"first").is_ok());
+        assert_eq!(
+            verifier.last_seen(),
+            Some(CertificateFingerprint::from_der(b"first"))
+        );
+
+        verifier.pin(CertificateFingerprint::from_der(b"first"));
+        assert!(verify(b"first").is_ok());
+        assert!(verify(b"second").is_err());
+    }
+
+    #[test]
+    pub fn test_check() {
+        let store = Arc::new(MemoryCertificateStore::default());
+        let first = CertificateFingerprint::from_der(b"first");
+        let second = CertificateFingerprint::from_der(b"second");
+        let server_name = ServerName::try_from("scanner.local").unwrap();
+
+        let pinning = CertificatePinning::new(store.clone(), None);
+        let verify = |pinning: &CertificatePinning, der: &[u8]| {
+            pinning.verifier.verify_server_cert(
+                &Certificate(der.to_vec()),
+                &[],
+                &server_name,
+                &mut std::iter::empty(),
+                &[],
+                SystemTime::now(),
+            )
+        };
+
+        // Nothing seen over plain HTTP
+        pinning.check("uuid").unwrap();
+        assert!(!pinning.is_pinned());
+        assert_eq!(store.get("uuid"), None);
+
+        // First contact
+        verify(&pinning, b"first").unwrap();
+        pinning.check("uuid").unwrap();
+        assert_eq!(store.get("uuid"), Some(first));
+        assert_eq!(pinning.verifier.pinned(), Some(first));
+
+        // Matching pin, from the store
+        let pinning = CertificatePinning::new(store.clone(), Some("uuid"));
+        assert!(pinning.is_pinned());
+        verify(&pinning, b"first").unwrap();
+        pinning.check("uuid").unwrap();
+        assert_eq!(pinning.verifier.pinned(), Some(first));
+
+        // Mismatch, with the stored certificate pinned in the end
+        let pinning = CertificatePinning::new(store.clone(), None);
+        verify(&pinning, b"second").unwrap();
+        match pinning.check("uuid") {
+            Err(Error::CertificateMismatch {
+                uuid,
+                expected,
+                actual,
+            }) => {
+                assert_eq!(uuid, "uuid");
+                assert_eq!(expected, first);
+                assert_eq!(actual, second);
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(store.get("uuid"), Some(first));
+        assert_eq!(pinning.verifier.pinned(), Some(first));
+        assert!(verify(&pinning, b"second").is_err());
+    }
+}
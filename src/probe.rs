@@ -0,0 +1,206 @@
+use std::{net::Ipv6Addr, time::Duration};
+
+use reqwest::{Client, StatusCode};
+use url::Url;
+
+use crate::{capabilities::ScannerCapabilities, extend_url, Error};
+
+/// Candidate eSCL resource paths, in the order they're probed.
+pub(crate) const CANDIDATE_ROOTS: [&str; 3] = ["eSCL", "escl", ""];
+
+/// Connect and request timeout of probing requests.
+pub(crate) const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Origins to probe for a manually entered `host`: the URL itself if it includes a scheme,
+/// otherwise `host` over HTTP, then HTTPS.
+pub(crate) fn probe_origins(host: &str) -> Vec<Url> {
+    match Url::parse(host) {
+        Ok(url) if url.has_host() => vec![url],
+        _ => {
+            // Bare IPv6 literals need brackets in URLs
+            let host = match host.parse::<Ipv6Addr>() {
+                Ok(_) => format!("[{}]", host),
+                Err(_) => host.to_owned(),
+            };
+            ["http", "https"]
+                .iter()
+                .filter_map(|scheme| Url::parse(&format!("{}://{}/", scheme, host)).ok())
+                .collect()
+        }
+    }
+}
+
+/// Probes `roots` under `origin` in order for a `ScannerCapabilities` document, returning the
+/// base URL of the first one serving it.
+pub(crate) async fn probe_roots<'a, I>(
+    http_client: &Client,
+    origin: &Url,
+    roots: I,
+) -> Result<(Url, ScannerCapabilities), Error>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut last_err = None;
+
+    for root in roots {
+        let mut url = origin.clone();
+        url.set_path(root);
+        url.set_query(None);
+        let url = extend_url(&url, &["ScannerCapabilities"]);
+
+        let response = match http_client.get(url).send().await {
+            Ok(response) => response,
+            // Nothing listening. Other roots won't do better.
+            Err(err) if err.is_connect() || err.is_timeout() => return Err(Error::Http(err)),
+            Err(err) => {
+                last_err = Some(Error::Http(err));
+                continue;
+            }
+        };
+
+        if response.status() != StatusCode::OK {
+            let err = Error::from_response(response).await;
+            if matches!(err, Error::Unauthorized(_)) {
+                return Err(err);
+            }
+            last_err = Some(err);
+            continue;
+        }
+
+        // Redirects may have led elsewhere
+        let mut base_url = response.url().clone();
+        base_url.set_query(None);
+        base_url
+            .path_segments_mut()
+            .expect("Invalid base URL")
+            .pop();
+
+        let response_body = match response.text().await {
+            Ok(response_body) => response_body,
+            Err(err) => {
+                last_err = Some(Error::Http(err));
+                continue;
+            }
+        };
+
+        match serde_xml_rs::from_str(&response_body) {
+            Ok(capabilities) => return Ok((base_url, capabilities)),
+            Err(err) => last_err = Some(Error::Xml(err)),
+        }
+    }
+
+    Err(last_err.expect("No root probed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{pinning::MemoryCertificateStore, test_server, Scanner};
+
+    #[tokio::test]
+    pub async fn test_probe() {
+        // Redirects from `/escl` to `/scanner`, and serves capabilities there only
+        let address = test_server::serve(|request| match request.path.as_str() {
+            "/escl/ScannerCapabilities" => test_server::Response::new("302 Found")
+                .header("Location", "/scanner/ScannerCapabilities"),
+            "/scanner/ScannerCapabilities" => test_server::Response::new("200 OK")
+                .header("Content-Type", "text/xml")
+                .body(include_str!("../test-data/capabilities/canon_ts7450.xml")),
+            _ => test_server::Response::new("404 Not Found"),
+        })
+        .await;
+        let host = address.to_string();
+
+        let (scanner, capabilities) = Scanner::probe(&host).await.unwrap();
+        assert_eq!(
+            scanner.base_url().as_str(),
+            format!("http://{}/scanner", host)
+        );
+        assert_eq!(capabilities.make_and_model, "Canon TS7400 series");
+
+        assert!(matches!(
+            Scanner::probe("not a host").await,
+            Err(Error::InvalidHost(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_probe_origins() {
+        for (host, expected) in [
+            ("192.168.1.1", "192.168.1.1"),
+            ("scanner.local:8080", "scanner.local:8080"),
+            ("2001:db8::5", "[2001:db8::5]"),
+            ("fe80::1", "[fe80::1]"),
+            ("[2001:db8::5]:8080", "[2001:db8::5]:8080"),
+        ] {
+            assert_eq!(
+                probe_origins(host)
+                    .iter()
+                    .map(Url::as_str)
+                    .collect::<Vec<_>>(),
+                [
+                    format!("http://{}/", expected),
+                    format!("https://{}/", expected)
+                ]
+            );
+        }
+        assert_eq!(
+            probe_origins("https://192.168.1.1/eSCL")
+                .iter()
+                .map(Url::as_str)
+                .collect::<Vec<_>>(),
+            ["https://192.168.1.1/eSCL"]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_probe_https() {
+        let handler = |request: &test_server::Request| match request.path.as_str() {
+            "/eSCL/ScannerCapabilities" => test_server::Response::new("200 OK")
+                .body(include_str!("../test-data/capabilities/canon_ts7450.xml")),
+            "/eSCL/ScannerStatus" => test_server::Response::new("200 OK")
+                .body(include_str!("../test-data/status/canceled_job.xml")),
+            _ => test_server::Response::new("404 Not Found"),
+        };
+        let tls_address = test_server::serve_tls(
+            include_bytes!("../test-data/tls/cert1.der"),
+            include_bytes!("../test-data/tls/key1.der"),
+            handler,
+        )
+        .await;
+        let impostor_address = test_server::serve_tls(
+            include_bytes!("../test-data/tls/cert2.der"),
+            include_bytes!("../test-data/tls/key2.der"),
+            handler,
+        )
+        .await;
+
+        // Plain HTTP redirecting everything to HTTPS
+        let address = test_server::serve(move |request| {
+            test_server::Response::new("301 Moved Permanently").header(
+                "Location",
+                format!("https://{}{}", tls_address, request.path),
+            )
+        })
+        .await;
+
+        // The self-signed certificate is accepted, and pinned
+        let store = Arc::new(MemoryCertificateStore::default());
+        let (scanner, capabilities) =
+            Scanner::probe_with_pinning(&address.to_string(), store.clone())
+                .await
+                .unwrap();
+        assert_eq!(
+            scanner.base_url().as_str(),
+            format!("https://{}/eSCL", tls_address)
+        );
+        scanner.status().await.unwrap();
+
+        match Scanner::probe_with_pinning(&format!("https://{}", impostor_address), store).await {
+            Err(Error::CertificateMismatch { uuid, .. }) => assert_eq!(uuid, capabilities.uuid),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::Error;
+
+/// Policy for retrying operations that the scanner temporarily refuses to serve, most notably
+/// `503 Service Unavailable` responses to `NextDocument` while a page is still being scanned, or
+/// to `ScanJobs` while the scanner is busy.
+///
+/// Retries are applied by [Scanner::scan](crate::Scanner::scan),
+/// [ScanJob::next_document](crate::ScanJob::next_document) and
+/// [ScanJob::next_document_stream](crate::ScanJob::next_document_stream).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one. A value of `1` disables retrying.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between two attempts.
+    pub max_backoff: Duration,
+    /// Factor applied to the delay after each retry. Values below `1.0` are treated as `1.0`,
+    /// i.e. a constant delay.
+    pub backoff_multiplier: f64,
+    /// Overall time limit after which no more retries are attempted, regardless of
+    /// `max_attempts`.
+    pub deadline: Option<Duration>,
+    /// Whether to wait for the duration requested by the scanner via the `Retry-After` header
+    /// instead of the computed backoff. The wait is still capped by `max_backoff`.
+    pub honor_retry_after: bool,
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn disabled() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Runs `operation` until it succeeds, fails with an error rejected by `should_retry`, or the
+    /// policy gives up. The last error is returned in the latter cases.
+    pub(crate) async fn run<T, F, Fut, P>(
+        &self,
+        mut operation: F,
+        should_retry: P,
+    ) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+        P: Fn(&Error) -> bool,
+    {
+        let started_at = Instant::now();
+        let mut attempt = 1;
+
+        loop {
+            let err = match operation().await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+
+            if attempt >= self.max_attempts || !should_retry(&err) {
+                return Err(err);
+            }
+
+            let delay = self.delay(attempt, &err);
+            if let Some(deadline) = self.deadline {
+                if started_at.elapsed() + delay > deadline {
+                    return Err(err);
+                }
+            }
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Computes the delay to wait after the `attempt`-th attempt failed with `err`.
+    fn delay(&self, attempt: u32, err: &Error) -> Duration {
+        let retry_after = if self.honor_retry_after {
+            err.response().and_then(|response| response.retry_after)
+        } else {
+            None
+        };
+
+        let delay = retry_after.unwrap_or_else(|| {
+            // `max` also gets rid of NaN
+            let factor = self
+                .backoff_multiplier
+                .max(1.0)
+                .powi(attempt.saturating_sub(1) as i32);
+            self.initial_backoff.mul_f64(factor.min(u32::MAX as f64))
+        });
+
+        delay.min(self.max_backoff)
+    }
+}
+
+/// Parses the value of a `Retry-After` header, either in seconds or as an HTTP date. Dates in the
+/// past result in no delay.
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+
+    match value.parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => {
+            let date = httpdate::parse_http_date(value).ok()?;
+            Some(
+                date.duration_since(SystemTime::now())
+                    .unwrap_or(Duration::ZERO),
+            )
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 30,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(5),
+            backoff_multiplier: 2.0,
+            deadline: Some(Duration::from_secs(120)),
+            honor_retry_after: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::*;
+    use crate::ErrorResponse;
+
+    #[test]
+    pub fn test_retry_delay() {
+        let policy = RetryPolicy::default();
+        let mut response = ErrorResponse {
+            url: "http://192.168.1.1/eSCL/ScanJobs/1/NextDocument"
+                .parse()
+                .unwrap(),
+            status_code: StatusCode::SERVICE_UNAVAILABLE,
+            body: String::new(),
+            retry_after: None,
+        };
+
+        let delays = (1..=6)
+            .map(|attempt| {
+                policy.delay(
+                    attempt,
+                    &Error::ServiceUnavailable(Box::new(response.clone())),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            [500, 1000, 2000, 4000, 5000, 5000].map(Duration::from_millis)
+        );
+
+        response.retry_after = Some(Duration::from_secs(3));
+        assert_eq!(
+            policy.delay(1, &Error::ServiceUnavailable(Box::new(response))),
+            Duration::from_secs(3)
+        );
+    }
+
+    #[test]
+    pub fn test_invalid_backoff_multiplier() {
+        let error = Error::LocationHeader;
+
+        for backoff_multiplier in [-2.0, 0.5, f64::NAN] {
+            let policy = RetryPolicy {
+                backoff_multiplier,
+                ..Default::default()
+            };
+            for attempt in 1..=3 {
+                assert_eq!(policy.delay(attempt, &error), Duration::from_millis(500));
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(60));
+        let delay = parse_retry_after(&date).unwrap();
+        assert!(delay > Duration::from_secs(55) && delay <= Duration::from_secs(60));
+    }
+}
@@ -1,6 +1,8 @@
+use std::fmt::Display;
+
 use serde::{de::Visitor, Deserialize, Serialize};
 
-use crate::capabilities::{ColorMode, ScanIntent};
+use crate::capabilities::{ColorMode, ScanIntent, ScannerCapabilities, SupportRange};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename = "scan:ScanSettings")]
@@ -49,12 +51,69 @@ pub struct ScanSettings {
         skip_serializing_if = "Option::is_none"
     )]
     pub color_mode: Option<ColorMode>,
+    /// Scans both sides of each sheet. Only valid with [InputSource::Feeder] on scanners
+    /// supporting it, see
+    /// [ScannerCapabilities::supports_duplex](crate::capabilities::ScannerCapabilities::supports_duplex).
+    #[serde(
+        rename = "scan:Duplex",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub duplex: Option<bool>,
+    #[serde(
+        rename = "scan:Brightness",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub brightness: Option<u32>,
     #[serde(
         rename = "scan:CompressionFactor",
         default,
         skip_serializing_if = "Option::is_none"
     )]
     pub compression_factor: Option<u32>,
+    #[serde(
+        rename = "scan:Contrast",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub contrast: Option<u32>,
+    #[serde(
+        rename = "scan:Gamma",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub gamma: Option<u32>,
+    #[serde(
+        rename = "scan:Highlight",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub highlight: Option<u32>,
+    #[serde(
+        rename = "scan:NoiseRemoval",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub noise_removal: Option<u32>,
+    #[serde(
+        rename = "scan:Shadow",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub shadow: Option<u32>,
+    #[serde(
+        rename = "scan:Sharpen",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub sharpen: Option<u32>,
+    #[serde(
+        rename = "scan:Threshold",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub threshold: Option<u32>,
     #[serde(
         rename = "scan:BlankPageDetection",
         default,
@@ -89,6 +148,35 @@ pub enum InputSource {
     Platen,
     /// ADF - Automatic Document Feeder
     Feeder,
+    /// Document camera
+    Camera,
+}
+
+/// A [ScanSettings] value rejected by [ScanSettings::validate].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SettingsError {
+    /// The scanner doesn't advertise a support range for the setting.
+    Unsupported { setting: &'static str },
+    /// The value is outside of the range advertised by the scanner, or not on one of its steps.
+    OutOfRange {
+        setting: &'static str,
+        value: u32,
+        range: SupportRange,
+    },
+}
+
+/// Order in which a scanner delivers the pages of a duplex scan job.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DuplexPageOrder {
+    /// Front and back of each sheet in turn, which is the reading order. This is what the eSCL
+    /// specification mandates.
+    #[default]
+    Interleaved,
+    /// All fronts, then all backs in the same sheet order.
+    FrontsThenBacks,
+    /// All fronts, then all backs in reverse sheet order, as produced by feeders that flip the
+    /// whole stack to scan the second side.
+    FrontsThenReversedBacks,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -99,6 +187,101 @@ pub enum ContentRegionUnits {
 struct InputSourceVisitor;
 struct ContentRegionUnitsVisitor;
 
+impl ScanSettings {
+    /// Checks the numeric image adjustment settings (brightness, contrast, compression factor...)
+    /// against the support ranges advertised in `capabilities`. Settings left to `None` are not
+    /// checked.
+    pub fn validate(&self, capabilities: &ScannerCapabilities) -> Result<(), SettingsError> {
+        let checks = [
+            (
+                "Brightness",
+                self.brightness,
+                capabilities.brightness_support,
+            ),
+            (
+                "CompressionFactor",
+                self.compression_factor,
+                capabilities.compression_factor_support,
+            ),
+            ("Contrast", self.contrast, capabilities.contrast_support),
+            ("Gamma", self.gamma, capabilities.gamma_support),
+            ("Highlight", self.highlight, capabilities.highlight_support),
+            (
+                "NoiseRemoval",
+                self.noise_removal,
+                capabilities.noise_removal_support,
+            ),
+            ("Shadow", self.shadow, capabilities.shadow_support),
+            ("Sharpen", self.sharpen, capabilities.sharpen_support),
+            ("Threshold", self.threshold, capabilities.threshold_support),
+        ];
+
+        for (setting, value, range) in checks {
+            let Some(value) = value else {
+                continue;
+            };
+
+            match range {
+                None => return Err(SettingsError::Unsupported { setting }),
+                Some(range) if !range.contains(value) => {
+                    return Err(SettingsError::OutOfRange {
+                        setting,
+                        value,
+                        range,
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl DuplexPageOrder {
+    /// Rearranges `pages`, delivered in this order, into reading order (front and back of each
+    /// sheet in turn). When the page count is odd, the last sheet is assumed to have no back.
+    pub fn reorder<T>(self, pages: Vec<T>) -> Vec<T> {
+        if self == Self::Interleaved {
+            return pages;
+        }
+
+        let mut fronts = pages;
+        let mut backs = fronts.split_off(fronts.len().div_ceil(2));
+        if self == Self::FrontsThenReversedBacks {
+            backs.reverse();
+        }
+
+        let mut pages = Vec::with_capacity(fronts.len() + backs.len());
+        let mut backs = backs.into_iter();
+        for front in fronts {
+            pages.push(front);
+            pages.extend(backs.next());
+        }
+
+        pages
+    }
+}
+
+impl Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsupported { setting } => write!(f, "{} is not supported", setting),
+            Self::OutOfRange {
+                setting,
+                value,
+                range,
+            } => write!(
+                f,
+                "{} {} is not within {}..={} in steps of {}",
+                setting, value, range.min, range.max, range.step
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
 impl Serialize for InputSource {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -107,6 +290,7 @@ impl Serialize for InputSource {
         serializer.serialize_str(match self {
             Self::Platen => "Platen",
             Self::Feeder => "Feeder",
+            Self::Camera => "Camera",
         })
     }
 }
@@ -134,6 +318,7 @@ impl<'de> Visitor<'de> for InputSourceVisitor {
         Ok(match v {
             "Platen" => InputSource::Platen,
             "Feeder" => InputSource::Feeder,
+            "Camera" => InputSource::Camera,
             _ => {
                 return Err(serde::de::Error::invalid_value(
                     serde::de::Unexpected::Str(v),
@@ -186,3 +371,86 @@ impl<'de> Visitor<'de> for ContentRegionUnitsVisitor {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_validate() {
+        let capabilities = serde_xml_rs::from_str::<ScannerCapabilities>(include_str!(
+            "../test-data/capabilities/duplex_adf.xml"
+        ))
+        .expect("capabilities deserializing failure");
+
+        let mut settings = ScanSettings {
+            version: capabilities.version.clone(),
+            intent: None,
+            scan_regions: None,
+            document_format_ext: None,
+            input_source: Some(InputSource::Feeder),
+            x_resolution: None,
+            y_resolution: None,
+            color_mode: None,
+            duplex: Some(true),
+            brightness: Some(60),
+            compression_factor: None,
+            contrast: None,
+            gamma: Some(220),
+            highlight: None,
+            noise_removal: None,
+            shadow: None,
+            sharpen: Some(2),
+            threshold: None,
+            blank_page_detection: None,
+        };
+        assert_eq!(settings.validate(&capabilities), Ok(()));
+
+        settings.sharpen = Some(3);
+        assert_eq!(
+            settings.validate(&capabilities),
+            Err(SettingsError::OutOfRange {
+                setting: "Sharpen",
+                value: 3,
+                range: capabilities.sharpen_support.unwrap(),
+            })
+        );
+
+        settings.sharpen = None;
+        settings.compression_factor = Some(1);
+        assert_eq!(
+            settings.validate(&capabilities),
+            Err(SettingsError::Unsupported {
+                setting: "CompressionFactor"
+            })
+        );
+
+        let xml = serde_xml_rs::to_string(&ScanSettings {
+            compression_factor: None,
+            ..settings
+        })
+        .unwrap();
+        assert!(xml.contains("<scan:Brightness>60</scan:Brightness>"));
+        assert!(xml.contains("<scan:Gamma>220</scan:Gamma>"));
+        assert!(!xml.contains("Sharpen"));
+    }
+
+    #[test]
+    pub fn test_duplex_page_order() {
+        assert_eq!(
+            DuplexPageOrder::Interleaved.reorder(vec![1, 2, 3, 4]),
+            [1, 2, 3, 4]
+        );
+        assert_eq!(
+            DuplexPageOrder::FrontsThenBacks.reorder(vec![1, 3, 5, 2, 4]),
+            [1, 2, 3, 4, 5]
+        );
+        assert_eq!(
+            DuplexPageOrder::FrontsThenReversedBacks.reorder(vec![1, 3, 5, 6, 4, 2]),
+            [1, 2, 3, 4, 5, 6]
+        );
+        assert!(DuplexPageOrder::FrontsThenBacks
+            .reorder(Vec::<u8>::new())
+            .is_empty());
+    }
+}
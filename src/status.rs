@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use url::Url;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -33,7 +34,12 @@ pub struct Jobs {
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct JobInfo {
+    /// Job URI as reported by the scanner, usually an absolute path (e.g. `/eSCL/ScanJobs/<uuid>`)
     pub job_uri: String,
+    /// [JobInfo::job_uri] resolved into an absolute URL, as done by
+    /// [Scanner::status](crate::Scanner::status) or [ScannerStatus::resolve_job_urls]
+    #[serde(skip)]
+    pub job_url: Option<Url>,
     pub job_uuid: String,
     pub age: u32,
     pub images_completed: u32,
@@ -65,8 +71,106 @@ pub struct JobStateReasons {
     pub job_state_reason: String,
 }
 
+impl ScannerStatus {
+    /// Looks up the [JobInfo] entry of the job identified by `job_url`, which is normally obtained
+    /// from [ScanJob::job_url](crate::ScanJob::job_url).
+    ///
+    /// Entries are matched against the path of `job_url`, as the resolved [JobInfo::job_url] may
+    /// not be available and scanners report job URIs in various forms. Only when no path matches,
+    /// the last path segment is compared with the job UUID as a whole.
+    pub fn find_job(&self, job_url: &Url) -> Option<&JobInfo> {
+        let job_path = job_url.path().trim_end_matches('/');
+        let job_id = job_path.rsplit('/').next().unwrap_or_default();
+
+        self.jobs
+            .job_info
+            .iter()
+            .find(|job| {
+                let job_uri = job.job_uri.trim_end_matches('/');
+
+                job.job_url
+                    .as_ref()
+                    .is_some_and(|url| url.path().trim_end_matches('/') == job_path)
+                    || job_uri == job_path
+                    || job_uri == job_url.as_str().trim_end_matches('/')
+            })
+            .or_else(|| {
+                self.jobs.job_info.iter().find(|job| {
+                    let job_uuid = job.job_uuid.trim();
+                    let job_uuid = job_uuid.strip_prefix("urn:uuid:").unwrap_or(job_uuid);
+
+                    !job_id.is_empty() && job_uuid.eq_ignore_ascii_case(job_id)
+                })
+            })
+    }
+
+    /// Resolves the URI of every job into [JobInfo::job_url], against `status_url`, the URL the
+    /// status was fetched from. See [Scanner::status](crate::Scanner::status) for how mismatching
+    /// hosts are handled.
+    pub fn resolve_job_urls(&mut self, status_url: &Url) {
+        for job in self.jobs.job_info.iter_mut() {
+            job.job_url = crate::resolve_url(status_url, &job.job_uri);
+        }
+    }
+}
+
 impl Jobs {
     fn is_empty(&self) -> bool {
         self.job_info.is_empty()
     }
 }
+
+impl JobState {
+    /// Whether the job has reached an end state and will no longer change.
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Self::Canceled | Self::Aborted | Self::Completed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_find_canceled_job() {
+        let status = serde_xml_rs::from_str::<ScannerStatus>(include_str!(
+            "../test-data/status/canceled_job.xml"
+        ))
+        .expect("status deserializing failure");
+
+        let job_url =
+            Url::parse("http://192.168.1.1/eSCL/ScanJobs/0f6b3fd6-4d0e-4b52-a6f2-4bb2cbd7c4a1")
+                .unwrap();
+
+        let job = status.find_job(&job_url).expect("job not found");
+        assert_eq!(job.job_state, JobState::Canceled);
+        assert!(job.job_state.is_terminal());
+
+        let mut status = status.clone();
+        status.resolve_job_urls(&Url::parse("http://192.168.1.1/eSCL/ScannerStatus").unwrap());
+        assert_eq!(status.jobs.job_info[0].job_url.as_ref(), Some(&job_url));
+    }
+
+    #[test]
+    pub fn test_find_job_by_uuid() {
+        let status = serde_xml_rs::from_str::<ScannerStatus>(include_str!(
+            "../test-data/status/canceled_job.xml"
+        ))
+        .expect("status deserializing failure");
+
+        // Job URI in a different form than reported, but with the UUID as last segment
+        let job_url =
+            Url::parse("http://192.168.1.1/scan/jobs/9A1C5E02-77D3-4C1B-8F0E-5D9A2E6B3C10/")
+                .unwrap();
+        let job = status.find_job(&job_url).expect("job not found");
+        assert_eq!(job.job_state, JobState::Completed);
+
+        // Short ids only match the end of UUIDs
+        for job_url in [
+            "http://192.168.1.1/eSCL/ScanJobs/1",
+            "http://192.168.1.1/eSCL/ScanJobs/c7c4a1",
+        ] {
+            assert_eq!(status.find_job(&Url::parse(job_url).unwrap()), None);
+        }
+    }
+}
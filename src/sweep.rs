@@ -0,0 +1,249 @@
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use futures_util::stream::{self, StreamExt};
+use ipnet::IpNet;
+use reqwest::Client;
+use url::{Host, Url};
+
+use crate::{
+    discovery::{DiscoverError, ScannerService},
+    probe::{probe_roots, CANDIDATE_ROOTS},
+    Error,
+};
+
+/// Number of host bits of the largest network that can be swept.
+const MAX_HOST_BITS: u8 = 16;
+
+/// Looks for eSCL-enabled scanners by probing every address of a network range, for networks
+/// where multicast is filtered and [discover](crate::discover) finds nothing.
+///
+/// Each address and port is probed for a `ScannerCapabilities` document under `/eSCL`, `/escl`
+/// and the root path. Port `443` is probed over HTTPS, all other ports over plain HTTP, then over
+/// HTTPS if plain HTTP gets no usable response. Certificates are left unverified.
+///
+/// Networks of more than 65536 addresses, i.e. larger than a `/16` for IPv4 or a `/112` for IPv6,
+/// are rejected with [DiscoverError::NetworkTooLarge].
+///
+/// ```no_run
+/// # async fn run() {
+/// use std::time::Duration;
+///
+/// use escl::sweep::Sweep;
+///
+/// let services = Sweep::new("192.168.1.0/24".parse().unwrap())
+///     .ports(&[80, 443, 8080])
+///     .timeout(Duration::from_millis(500))
+///     .run()
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sweep {
+    network: IpNet,
+    ports: Vec<u16>,
+    concurrency: usize,
+    timeout: Duration,
+}
+
+impl Sweep {
+    /// Creates a sweep of all host addresses in `network`, on ports `80` and `443`, probing up to
+    /// 64 addresses at a time with a timeout of 1 second.
+    pub fn new(network: IpNet) -> Self {
+        Self {
+            network,
+            ports: vec![80, 443],
+            concurrency: 64,
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    pub fn ports(mut self, ports: &[u16]) -> Self {
+        self.ports = ports.to_vec();
+        self
+    }
+
+    /// Maximum number of addresses and ports probed at the same time.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Timeout of each probe request, including connecting.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Probes the network range. Scanners answering on several ports are merged by UUID, with
+    /// HTTPS ranked first. Addresses that can't be reached are skipped.
+    pub async fn run(self) -> Result<Vec<ScannerService>, DiscoverError> {
+        if self.network.max_prefix_len() - self.network.prefix_len() > MAX_HOST_BITS {
+            return Err(DiscoverError::NetworkTooLarge(self.network));
+        }
+
+        let http_client = Client::builder()
+            .connect_timeout(self.timeout)
+            .timeout(self.timeout)
+            .danger_accept_invalid_certs(true)
+            .build()
+            .map_err(DiscoverError::Http)?;
+
+        // Addresses are generated as probes complete
+        let ports = &self.ports;
+        let addresses = self.network.hosts().flat_map(|ip_addr| {
+            ports
+                .iter()
+                .map(move |port| SocketAddr::new(ip_addr, *port))
+        });
+
+        let mut found = stream::iter(addresses)
+            .map(|address| probe(&http_client, address))
+            .buffer_unordered(self.concurrency)
+            .filter_map(|service| async move { service })
+            .collect::<Vec<_>>()
+            .await;
+        found.sort_by_key(|service| (!service.is_tls(), service.addresses()[0]));
+
+        let mut services: Vec<ScannerService> = vec![];
+        let mut indices: HashMap<String, usize> = HashMap::new();
+        for service in found {
+            match indices.get(service.device_key()) {
+                Some(&ind) => services[ind].merge(service),
+                None => {
+                    indices.insert(service.device_key().to_owned(), services.len());
+                    services.push(service);
+                }
+            }
+        }
+        services.sort_by_key(|service| service.addresses()[0].ip());
+
+        Ok(services)
+    }
+}
+
+/// Probes the candidate roots on a single address, returning the first one serving valid
+/// scanner capabilities.
+async fn probe(http_client: &Client, address: SocketAddr) -> Option<ScannerService> {
+    let schemes: &[&str] = if address.port() == 443 {
+        &["https"]
+    } else {
+        &["http", "https"]
+    };
+
+    for scheme in schemes {
+        let origin = Url::parse(&format!("{}://{}/", scheme, address)).ok()?;
+        match probe_roots(http_client, &origin, CANDIDATE_ROOTS).await {
+            Ok((base_url, capabilities)) => {
+                // Redirects may have led to another host or port
+                let address = url_address(&base_url).await?;
+                return Some(ScannerService::from_capabilities(
+                    base_url,
+                    address,
+                    &capabilities,
+                ));
+            }
+            // Nothing listening
+            Err(Error::Http(err)) if err.is_connect() || err.is_timeout() => return None,
+            Err(Error::Unauthorized(_)) => return None,
+            Err(_) => continue,
+        }
+    }
+
+    None
+}
+
+/// Socket address `url` points to, resolving its host name if needed.
+async fn url_address(url: &Url) -> Option<SocketAddr> {
+    let port = url.port_or_known_default()?;
+    match url.host()? {
+        Host::Ipv4(ip_addr) => Some(SocketAddr::new(ip_addr.into(), port)),
+        Host::Ipv6(ip_addr) => Some(SocketAddr::new(ip_addr.into(), port)),
+        Host::Domain(domain) => tokio::net::lookup_host((domain, port)).await.ok()?.next(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[tokio::test]
+    pub async fn test_sweep() {
+        // Serves capabilities under `/escl` only
+        let address = test_server::serve(|request| match request.path.as_str() {
+            "/escl/ScannerCapabilities" => test_server::Response::new("200 OK")
+                .header("Content-Type", "text/xml")
+                .body(include_str!(
+                    "../test-data/capabilities/brother_mfc_j497dw.xml"
+                )),
+            _ => test_server::Response::new("404 Not Found"),
+        })
+        .await;
+        let port = address.port();
+
+        let services = Sweep::new("127.0.0.1/32".parse().unwrap())
+            .ports(&[port])
+            .run()
+            .await
+            .unwrap();
+
+        assert_eq!(services.len(), 1);
+        assert_eq!(
+            services[0].url().as_str(),
+            format!("http://127.0.0.1:{}/escl", port)
+        );
+        assert_eq!(services[0].name(), "Brother MFC-J497DW");
+        assert_eq!(services[0].txt().rs.as_deref(), Some("escl"));
+        assert_eq!(services[0].txt().input_sources, ["platen", "adf"]);
+    }
+
+    #[tokio::test]
+    pub async fn test_sweep_https() {
+        let tls_address = test_server::serve_tls(
+            include_bytes!("../test-data/tls/cert1.der"),
+            include_bytes!("../test-data/tls/key1.der"),
+            |request| match request.path.as_str() {
+                "/eSCL/ScannerCapabilities" => test_server::Response::new("200 OK")
+                    .body(include_str!("../test-data/capabilities/canon_ts7450.xml")),
+                _ => test_server::Response::new("404 Not Found"),
+            },
+        )
+        .await;
+        // Redirects everything to the HTTPS server
+        let address = test_server::serve(move |request| {
+            test_server::Response::new("301 Moved Permanently").header(
+                "Location",
+                format!("https://{}{}", tls_address, request.path),
+            )
+        })
+        .await;
+
+        // HTTPS on a port other than 443, and a redirect to another port
+        for port in [tls_address.port(), address.port()] {
+            let services = Sweep::new("127.0.0.1/32".parse().unwrap())
+                .ports(&[port])
+                .run()
+                .await
+                .unwrap();
+
+            assert_eq!(services.len(), 1);
+            assert_eq!(
+                services[0].url().as_str(),
+                format!("https://{}/eSCL", tls_address)
+            );
+            assert_eq!(services[0].addresses(), [tls_address]);
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_sweep_too_large() {
+        for network in ["10.0.0.0/15", "fd00::/64"] {
+            let network = network.parse().unwrap();
+            assert!(matches!(
+                Sweep::new(network).run().await,
+                Err(DiscoverError::NetworkTooLarge(rejected)) if rejected == network
+            ));
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Minimal HTTP/1.1 server standing in for scanners in tests.
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+};
+use tokio_rustls::{
+    rustls::{self, Certificate, PrivateKey},
+    TlsAcceptor,
+};
+
+/// A request received by the test server.
+#[derive(Debug)]
+pub(crate) struct Request {
+    pub method: String,
+    /// Path and query of the request target
+    pub path: String,
+    /// Header names are lowercase
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A response to send back. Each connection only serves a single request.
+#[derive(Debug)]
+pub(crate) struct Response {
+    status: &'static str,
+    headers: Vec<(String, String)>,
+    chunks: Vec<Vec<u8>>,
+    chunked: bool,
+}
+
+impl Response {
+    /// Creates an empty response with a status line such as `200 OK`.
+    pub fn new(status: &'static str) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            chunks: vec![],
+            chunked: false,
+        }
+    }
+
+    pub fn header<N, V>(mut self, name: N, value: V) -> Self
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets a body sent in one go, along with its `Content-Length`.
+    pub fn body<B>(mut self, body: B) -> Self
+    where
+        B: Into<Vec<u8>>,
+    {
+        self.chunks = vec![body.into()];
+        self.chunked = false;
+        self
+    }
+
+    /// Sets a body sent with chunked transfer encoding, pausing between chunks so that the client
+    /// receives them separately.
+    pub fn chunked(mut self, chunks: Vec<Vec<u8>>) -> Self {
+        self.chunks = chunks;
+        self.chunked = true;
+        self
+    }
+}
+
+/// Serves requests on an ephemeral port of the loopback interface with `handler`, until the test
+/// ends. Returns the address of the server.
+pub(crate) async fn serve<F>(handler: F) -> SocketAddr
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let handler = Arc::new(handler);
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let _ = handle_connection(stream, handler.as_ref()).await;
+            });
+        }
+    });
+
+    address
+}
+
+/// Same as [serve], but over TLS with a DER-encoded `certificate` and PKCS#8 `key`.
+pub(crate) async fn serve_tls<F>(certificate: &[u8], key: &[u8], handler: F) -> SocketAddr
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![Certificate(certificate.to_vec())],
+            PrivateKey(key.to_vec()),
+        )
+        .unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let handler = Arc::new(handler);
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                // Handshakes fail when clients reject the certificate
+                if let Ok(stream) = acceptor.accept(stream).await {
+                    let _ = handle_connection(stream, handler.as_ref()).await;
+                }
+            });
+        }
+    });
+
+    address
+}
+
+async fn handle_connection<S, F>(mut stream: S, handler: &F) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: Fn(&Request) -> Response,
+{
+    let mut data = vec![];
+    let mut buffer = [0; 4096];
+    let head_len = loop {
+        if let Some(pos) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        let len = stream.read(&mut buffer).await?;
+        if len == 0 {
+            return Ok(());
+        }
+        data.extend_from_slice(&buffer[..len]);
+    };
+
+    let head = String::from_utf8_lossy(&data[..head_len]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_owned();
+    let path = request_line.next().unwrap_or_default().to_owned();
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_owned()))
+        .collect::<Vec<_>>();
+
+    let content_length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or_default();
+    let mut body = data[head_len..].to_vec();
+    while body.len() < content_length {
+        let len = stream.read(&mut buffer).await?;
+        if len == 0 {
+            break;
+        }
+        body.extend_from_slice(&buffer[..len]);
+    }
+
+    let response = handler(&Request {
+        method,
+        path,
+        headers,
+        body,
+    });
+
+    let mut head = format!("HTTP/1.1 {}\r\nConnection: close\r\n", response.status);
+    for (name, value) in response.headers.iter() {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if response.chunked {
+        head.push_str("Transfer-Encoding: chunked\r\n\r\n");
+    } else {
+        let len = response.chunks.iter().map(Vec::len).sum::<usize>();
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", len));
+    }
+    stream.write_all(head.as_bytes()).await?;
+
+    for chunk in response.chunks.iter() {
+        if response.chunked {
+            stream
+                .write_all(format!("{:x}\r\n", chunk.len()).as_bytes())
+                .await?;
+            stream.write_all(chunk).await?;
+            stream.write_all(b"\r\n").await?;
+            stream.flush().await?;
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        } else {
+            stream.write_all(chunk).await?;
+        }
+    }
+    if response.chunked {
+        stream.write_all(b"0\r\n\r\n").await?;
+    }
+
+    stream.shutdown().await
+}